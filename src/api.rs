@@ -0,0 +1,484 @@
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use gearbox_maintenance::{
+    config::{
+        policy::{ConditionMatch, DeletePolicy},
+        Instance,
+    },
+    Torrent,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+/// How many on-demand tick requests may queue up per instance.
+const TICK_REQUEST_QUEUE: usize = 8;
+
+/// A torrent as it was last fetched from an instance.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct TorrentView {
+    hash: String,
+    name: String,
+    status: String,
+    error: Option<String>,
+    done_date: Option<DateTime<Utc>>,
+    upload_ratio: f32,
+    total_size: usize,
+    num_files: usize,
+    trackers: Vec<String>,
+    /// The first policy that governs this torrent.
+    policy: Option<String>,
+    /// What that policy decided about the torrent.
+    decision: Option<ConditionMatch>,
+}
+
+impl TorrentView {
+    pub(crate) fn new(torrent: &Torrent, governed: Option<(String, ConditionMatch)>) -> Self {
+        let (policy, decision) = governed.unzip();
+        TorrentView {
+            hash: torrent.hash.clone(),
+            name: torrent.name.clone(),
+            status: format!("{:?}", torrent.status),
+            error: (!torrent.is_ok()).then(|| torrent.error_string.clone()),
+            done_date: torrent.done_date,
+            upload_ratio: torrent.upload_ratio,
+            total_size: torrent.total_size,
+            num_files: torrent.num_files,
+            trackers: torrent.trackers.iter().map(|t| t.to_string()).collect(),
+            policy,
+            decision,
+        }
+    }
+}
+
+/// The outcome of a single tick.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct TickOutcome {
+    at: DateTime<Utc>,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// What is known about an instance from its most recent ticks.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InstanceStatus {
+    last_tick: Option<TickOutcome>,
+    last_success: Option<DateTime<Utc>>,
+    torrents: Vec<TorrentView>,
+}
+
+impl InstanceStatus {
+    pub(crate) fn record_tick(&mut self, at: DateTime<Utc>, result: &anyhow::Result<()>) {
+        if result.is_ok() {
+            self.last_success = Some(at);
+        }
+        self.last_tick = Some(TickOutcome {
+            at,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        });
+    }
+
+    pub(crate) fn set_torrents(&mut self, torrents: Vec<TorrentView>) {
+        self.torrents = torrents;
+    }
+}
+
+/// A request to run a tick right away, outside the regular schedule.
+#[derive(Debug, Default)]
+pub(crate) struct TickRequest {
+    /// If true, the tick doesn't take any action, even when running with `-f`.
+    pub(crate) dry_run: bool,
+
+    /// Receives the result of the tick once it's done.
+    pub(crate) done: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// An instance along with its status, shared between its tick loop
+/// and the API.
+#[derive(Debug)]
+pub(crate) struct InstanceEntry {
+    pub(crate) instance: Instance,
+    pub(crate) status: RwLock<InstanceStatus>,
+    requests: mpsc::Sender<TickRequest>,
+}
+
+impl InstanceEntry {
+    /// Returns the entry, and the receiving end for tick requests,
+    /// which the instance's tick loop should handle.
+    pub(crate) fn new(instance: Instance) -> (Arc<Self>, mpsc::Receiver<TickRequest>) {
+        let (requests, receiver) = mpsc::channel(TICK_REQUEST_QUEUE);
+        let entry = Arc::new(InstanceEntry {
+            instance,
+            status: Default::default(),
+            requests,
+        });
+        (entry, receiver)
+    }
+
+    /// Asks the instance's tick loop to run a tick as soon as the
+    /// current one (if any) is done, without waiting for it.
+    pub(crate) fn request_tick(&self) {
+        if self.requests.try_send(TickRequest::default()).is_err() {
+            tracing::warn!(
+                instance = self.instance.name(),
+                "Too many tick requests queued up, ignoring this one"
+            );
+        }
+    }
+
+    /// Runs a tick on the instance's tick loop and returns its result.
+    async fn tick_now(&self, dry_run: bool) -> Result<(), String> {
+        let (done, result) = oneshot::channel();
+        self.requests
+            .send(TickRequest {
+                dry_run,
+                done: Some(done),
+            })
+            .await
+            .map_err(|_| "Instance is not running".to_string())?;
+        result
+            .await
+            .map_err(|_| "Instance stopped before the tick was done".to_string())?
+    }
+
+    pub(crate) fn status(&self) -> InstanceStatus {
+        self.status
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub(crate) fn update_status(&self, f: impl FnOnce(&mut InstanceStatus)) {
+        f(&mut self.status.write().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[derive(Serialize)]
+struct InstanceView {
+    name: String,
+    url: String,
+    poll_interval_secs: i64,
+    policy_count: usize,
+    last_tick: Option<TickOutcome>,
+    last_success: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct PoliciesView<'a> {
+    instance: &'a str,
+    policies: &'a [DeletePolicy],
+}
+
+type Instances = Arc<Vec<Arc<InstanceEntry>>>;
+
+#[derive(Clone)]
+struct TickState {
+    instances: Instances,
+    allow_deletions: bool,
+}
+
+#[derive(Clone)]
+struct HealthState {
+    instances: Instances,
+    ready_poll_intervals: u32,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    name: String,
+    ready: bool,
+    last_success: Option<DateTime<Utc>>,
+    last_tick: Option<TickOutcome>,
+}
+
+async fn instances_handler(State(instances): State<Instances>) -> impl IntoResponse {
+    let views: Vec<InstanceView> = instances
+        .iter()
+        .map(|entry| {
+            let status = entry.status();
+            let transmission = &entry.instance.transmission;
+            InstanceView {
+                name: entry.instance.name().to_string(),
+                url: transmission.url.clone(),
+                poll_interval_secs: transmission.poll_interval().num_seconds(),
+                policy_count: entry.instance.policies.len(),
+                last_tick: status.last_tick,
+                last_success: status.last_success,
+            }
+        })
+        .collect();
+    Json(views)
+}
+
+async fn policies_handler(State(instances): State<Instances>) -> impl IntoResponse {
+    let views: Vec<PoliciesView> = instances
+        .iter()
+        .map(|entry| PoliciesView {
+            instance: entry.instance.name(),
+            policies: &entry.instance.policies,
+        })
+        .collect();
+    Json(serde_json::to_value(views).unwrap_or_default())
+}
+
+async fn torrents_handler(
+    State(instances): State<Instances>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match instances.iter().find(|entry| entry.instance.name() == name) {
+        Some(entry) => Json(entry.status().torrents).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No instance named {name:?}")).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TickParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn tick_handler(
+    State(state): State<TickState>,
+    Path(name): Path<String>,
+    Query(params): Query<TickParams>,
+) -> impl IntoResponse {
+    let Some(entry) = state
+        .instances
+        .iter()
+        .find(|entry| entry.instance.name() == name)
+    else {
+        return (StatusCode::NOT_FOUND, format!("No instance named {name:?}")).into_response();
+    };
+    // Anyone who can reach the API can request a tick, so those only
+    // delete anything if that was explicitly allowed:
+    let dry_run = params.dry_run || !state.allow_deletions;
+    let result = entry.tick_now(dry_run).await;
+    let code = if result.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    let body = serde_json::json!({
+        "instance": name,
+        "dry_run": dry_run,
+        "success": result.is_ok(),
+        "error": result.err(),
+    });
+    (code, Json(body)).into_response()
+}
+
+async fn healthz_handler() -> impl IntoResponse {
+    Json(serde_json::json!({"status": "ok"}))
+}
+
+async fn readyz_handler(State(state): State<HealthState>) -> impl IntoResponse {
+    let now = Utc::now();
+    let instances: Vec<Readiness> = state
+        .instances
+        .iter()
+        .map(|entry| {
+            let status = entry.status();
+            let max_age =
+                entry.instance.transmission.poll_interval() * state.ready_poll_intervals as i32;
+            Readiness {
+                name: entry.instance.name().to_string(),
+                ready: status
+                    .last_success
+                    .map(|at| now - at <= max_age)
+                    .unwrap_or(false),
+                last_success: status.last_success,
+                last_tick: status.last_tick,
+            }
+        })
+        .collect();
+    let ready = instances.iter().all(|i| i.ready);
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        code,
+        Json(serde_json::json!({"ready": ready, "instances": instances})),
+    )
+}
+
+/// JSON endpoints describing the configured instances and triggering
+/// ticks on them, plus health and readiness checks.
+///
+/// An instance counts as ready if it had a successful tick within
+/// the last `ready_poll_intervals` poll intervals. Ticks requested
+/// through the API are dry runs unless `allow_deletions` is set.
+pub(crate) fn api_router(
+    instances: Vec<Arc<InstanceEntry>>,
+    ready_poll_intervals: u32,
+    allow_deletions: bool,
+) -> Router {
+    let instances = Arc::new(instances);
+    let ticks = Router::new()
+        .route("/api/instances/{name}/tick", post(tick_handler))
+        .with_state(TickState {
+            instances: instances.clone(),
+            allow_deletions,
+        });
+    let health = Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .with_state(HealthState {
+            instances: instances.clone(),
+            ready_poll_intervals,
+        });
+    Router::new()
+        .route("/api/instances", get(instances_handler))
+        .route("/api/policies", get(policies_handler))
+        .route("/api/instances/{name}/torrents", get(torrents_handler))
+        .with_state(instances)
+        .merge(ticks)
+        .merge(health)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gearbox_maintenance::config::transmission::Transmission;
+
+    async fn serve_with(instances: Vec<Arc<InstanceEntry>>, allow_deletions: bool) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let router = api_router(instances, 3, allow_deletions);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        base
+    }
+
+    async fn serve(instances: Vec<Arc<InstanceEntry>>) -> String {
+        serve_with(instances, false).await
+    }
+
+    #[tokio::test]
+    async fn lists_instances_and_torrents() {
+        let (entry, _requests) = InstanceEntry::new(Instance {
+            transmission: Transmission::new("seedbox").with_password("hunter2"),
+            policies: vec![],
+            inherited: Default::default(),
+        });
+        entry.update_status(|status| status.record_tick(Utc::now(), &Ok(())));
+        let base = serve(vec![entry]).await;
+
+        let instances: serde_json::Value = reqwest::get(format!("{base}/api/instances"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(instances[0]["name"], "seedbox");
+        assert_eq!(instances[0]["last_tick"]["success"], true);
+        assert!(!instances.to_string().contains("hunter2"));
+
+        let torrents = reqwest::get(format!("{base}/api/instances/seedbox/torrents"))
+            .await
+            .unwrap();
+        assert_eq!(torrents.status(), StatusCode::OK);
+        let missing = reqwest::get(format!("{base}/api/instances/nope/torrents"))
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn readiness_follows_successful_ticks() {
+        let (fresh, _fresh_requests) = InstanceEntry::new(Instance {
+            transmission: Transmission::new("fresh"),
+            policies: vec![],
+            inherited: Default::default(),
+        });
+        let (stale, _stale_requests) = InstanceEntry::new(Instance {
+            transmission: Transmission::new("stale"),
+            policies: vec![],
+            inherited: Default::default(),
+        });
+        fresh.update_status(|status| status.record_tick(Utc::now(), &Ok(())));
+        stale.update_status(|status| {
+            status.record_tick(Utc::now() - chrono::Duration::hours(1), &Ok(()));
+            status.record_tick(Utc::now(), &Err(anyhow::anyhow!("unreachable")));
+        });
+        let base = serve(vec![fresh.clone(), stale.clone()]).await;
+
+        let health = reqwest::get(format!("{base}/healthz")).await.unwrap();
+        assert_eq!(health.status(), StatusCode::OK);
+
+        let ready = reqwest::get(format!("{base}/readyz")).await.unwrap();
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = ready.json().await.unwrap();
+        assert_eq!(body["instances"][0]["ready"], true);
+        assert_eq!(body["instances"][1]["ready"], false);
+        assert_eq!(body["instances"][1]["last_tick"]["error"], "unreachable");
+
+        stale.update_status(|status| status.record_tick(Utc::now(), &Ok(())));
+        let ready = reqwest::get(format!("{base}/readyz")).await.unwrap();
+        assert_eq!(ready.status(), StatusCode::OK);
+    }
+
+    /// An instance whose tick loop fails every tick that isn't a dry run.
+    fn refusing_instance() -> Arc<InstanceEntry> {
+        let (entry, mut requests) = InstanceEntry::new(Instance {
+            transmission: Transmission::new("seedbox"),
+            policies: vec![],
+            inherited: Default::default(),
+        });
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                let result = if request.dry_run {
+                    Ok(())
+                } else {
+                    Err("would have deleted".to_string())
+                };
+                request.done.unwrap().send(result).unwrap();
+            }
+        });
+        entry
+    }
+
+    #[tokio::test]
+    async fn triggers_ticks() {
+        let base = serve_with(vec![refusing_instance()], true).await;
+        let client = reqwest::Client::new();
+
+        let dry = client
+            .post(format!("{base}/api/instances/seedbox/tick?dry_run=true"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(dry.status(), StatusCode::OK);
+
+        let real = client
+            .post(format!("{base}/api/instances/seedbox/tick"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(real.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = real.json().await.unwrap();
+        assert_eq!(body["error"], "would have deleted");
+    }
+
+    #[tokio::test]
+    async fn requested_ticks_are_dry_runs_unless_allowed() {
+        let base = serve(vec![refusing_instance()]).await;
+        let response = reqwest::Client::new()
+            .post(format!("{base}/api/instances/seedbox/tick"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["dry_run"], true);
+    }
+}
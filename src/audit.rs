@@ -0,0 +1,154 @@
+//! An append-only log of every action that was executed on a torrent.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::policy::ConditionMatch;
+use crate::limits::DeletionCandidate;
+
+/// One executed action, as it appears in the audit log.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub instance: String,
+    pub policy: String,
+    pub hash: String,
+    pub name: String,
+    pub trackers: Vec<String>,
+    pub total_size: usize,
+    pub upload_ratio: f32,
+    /// How long the torrent had been seeding, in seconds.
+    pub seeding_time: Option<i64>,
+    pub reason: ConditionMatch,
+    /// Whether the torrent's data was trashed along with it.
+    pub delete_data: bool,
+    /// Whether transmission accepted the request.
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    /// Describes the outcome of acting on `candidate` at `now`.
+    pub fn new(
+        now: DateTime<Utc>,
+        instance: &str,
+        candidate: &DeletionCandidate<'_>,
+        error: Option<String>,
+    ) -> Self {
+        let torrent = candidate.torrent;
+        AuditRecord {
+            timestamp: now,
+            instance: instance.to_string(),
+            policy: candidate.policy_name(),
+            hash: torrent.hash.clone(),
+            name: torrent.name.clone(),
+            trackers: torrent.trackers.iter().map(|t| t.to_string()).collect(),
+            total_size: torrent.total_size,
+            upload_ratio: torrent.upload_ratio,
+            seeding_time: torrent.done_date.map(|d| (now - d).num_seconds()),
+            reason: candidate.reason,
+            delete_data: candidate.policy.delete_data,
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// A JSON-lines file that audit records get appended to.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, creating it if necessary.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(AuditLog {
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Appends `record` to the log and syncs it to disk.
+    pub fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::policy::{Condition, DeletePolicy, PolicyMatch};
+    use crate::Torrent;
+    use chrono::Duration;
+    use std::io::{BufRead, BufReader};
+    use transmission_rpc::types::{ErrorType, TorrentStatus};
+    use url::Url;
+
+    #[test]
+    fn appends_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let now = Utc::now();
+        let torrent = Torrent {
+            id: 1,
+            hash: "abcd".to_string(),
+            name: "testcase".to_string(),
+            done_date: Some(now - Duration::days(2)),
+            error: ErrorType::Ok,
+            error_string: "".to_string(),
+            upload_ratio: 2.0,
+            computed_upload_ratio: 2.0,
+            uploaded_ever: 60000,
+            status: TorrentStatus::Seeding,
+            num_files: 1,
+            total_size: 30000,
+            trackers: vec![Url::parse("https://tracker:8080/announce").unwrap()],
+        };
+        let policy = DeletePolicy::new_real(
+            "ratio",
+            PolicyMatch::default(),
+            Condition::new().unwrap().with_max_ratio(1.0),
+        )
+        .unwrap();
+        let candidate = DeletionCandidate {
+            torrent: &torrent,
+            policy_index: 0,
+            policy: &policy,
+            reason: ConditionMatch::Ratio(2.0),
+        };
+
+        let log = AuditLog::open(&path).unwrap();
+        log.append(&AuditRecord::new(now, "inst", &candidate, None))
+            .unwrap();
+        log.append(&AuditRecord::new(
+            now,
+            "inst",
+            &candidate,
+            Some("no".to_string()),
+        ))
+        .unwrap();
+
+        let records: Vec<AuditRecord> = BufReader::new(File::open(&path).unwrap())
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].policy, "ratio");
+        assert_eq!(records[0].seeding_time, Some(2 * 24 * 60 * 60));
+        assert_eq!(records[0].trackers, vec!["https://tracker:8080/announce"]);
+        assert!(records[0].delete_data);
+        assert!(records[0].success);
+        assert!(!records[1].success);
+        assert_eq!(records[1].error.as_deref(), Some("no"));
+    }
+}
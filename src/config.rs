@@ -1,16 +1,57 @@
+pub mod defaults;
+pub mod format;
+pub mod limits;
+pub mod lint;
 pub mod policy;
-mod transmission;
+pub mod rpc;
+pub mod secret;
+pub mod tls;
+pub mod transmission;
+pub mod units;
+pub mod webhook;
+pub mod window;
 
+use self::defaults::Defaults;
+use self::format::{ConfigFormat, DeclarativeConfig};
 use self::policy::{Condition, PolicyMatch};
+use self::units::{ByteSize, TimeSpan};
 use crate::config::policy::DeletePolicy;
 use crate::config::transmission::Transmission;
+use crate::config::webhook::Webhook;
 use rhai::{module_resolvers::FileModuleResolver, Array};
 use rhai::{CustomType, TypeBuilder};
 use rhai::{Dynamic, Engine, EvalAltResult};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
 use std::path::{Path, PathBuf};
 
+/// Loads the config in `file`, in the format that its extension
+/// suggests.
 pub fn configure(file: &Path) -> Result<Vec<Instance>, Box<EvalAltResult>> {
+    configure_as(file, ConfigFormat::from_path(file))
+}
+
+/// Loads the config in `file`, written in `format`.
+pub fn configure_as(
+    file: &Path,
+    format: ConfigFormat,
+) -> Result<Vec<Instance>, Box<EvalAltResult>> {
+    let config = match format {
+        ConfigFormat::Rhai => evaluate(file)?,
+        _ => {
+            let contents = std::fs::read_to_string(file)
+                .map_err(|e| format!("Could not read config {file:?}: {e}"))?;
+            format
+                .parse(&contents)
+                .map_err(|e| format!("Could not parse {format} config {file:?}: {e}"))?
+        }
+    };
+    resolve(file, config)
+}
+
+/// Evaluates a Rhai config.
+fn evaluate(file: &Path) -> Result<DeclarativeConfig, Box<EvalAltResult>> {
     let mut engine = Engine::new();
     let resolver = FileModuleResolver::new_with_path(file.parent().unwrap_or(&PathBuf::from(".")));
     engine.set_module_resolver(resolver);
@@ -19,26 +60,102 @@ pub fn configure(file: &Path) -> Result<Vec<Instance>, Box<EvalAltResult>> {
         .build_type::<Transmission>()
         // Instances:
         .build_type::<Instance>()
+        // Settings that instances and policies inherit:
+        .build_type::<Defaults>()
+        // Notifications:
+        .build_type::<Webhook>()
         // Policies
         .build_type::<PolicyMatch>()
         .build_type::<DeletePolicy>()
         // Conditions
-        .build_type::<Condition>();
-
-    Dynamic::from(
-        engine
-            .eval_file::<Array>(file.to_owned())
-            .map_err(|e| format!("Could not eval config {file:?}: {e}"))?,
-    )
-    .into_typed_array()
-    .map_err(|e| e.to_string().into())
+        .build_type::<Condition>()
+        // Durations and byte sizes:
+        .build_type::<TimeSpan>()
+        .build_type::<ByteSize>();
+
+    let items = engine
+        .eval_file::<Array>(file.to_owned())
+        .map_err(|e| format!("Could not eval config {file:?}: {e}"))?;
+
+    let mut config = DeclarativeConfig::default();
+    for item in items {
+        let item = match item.try_cast_result::<Instance>() {
+            Ok(instance) => {
+                config.instances.push(instance);
+                continue;
+            }
+            Err(item) => item,
+        };
+        let item = match item.try_cast_result::<Webhook>() {
+            Ok(webhook) => {
+                config.webhooks.push(webhook);
+                continue;
+            }
+            Err(item) => item,
+        };
+        match item.try_cast_result::<Defaults>() {
+            Ok(defaults) => {
+                if config.defaults.replace(defaults).is_some() {
+                    Err(format!("Config {file:?} contains more than one defaults()"))?;
+                }
+            }
+            Err(item) => Err(format!(
+                "Config {file:?} contains a {}, expected only instances, webhooks and defaults",
+                item.type_name()
+            ))?,
+        }
+    }
+    Ok(config)
 }
 
-#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, CustomType)]
+/// Turns a config into the instances it describes, and checks that
+/// they make sense, no matter which format the config was written in.
+fn resolve(file: &Path, config: DeclarativeConfig) -> Result<Vec<Instance>, Box<EvalAltResult>> {
+    let DeclarativeConfig {
+        defaults,
+        webhooks,
+        mut instances,
+    } = config;
+    let mut names = HashSet::new();
+    for instance in instances.iter() {
+        if !names.insert(instance.name()) {
+            Err(format!(
+                "Config {file:?} contains more than one instance named {:?}",
+                instance.name()
+            ))?;
+        }
+    }
+    // Webhooks at the top level apply to every instance, and
+    // defaults fill in what instances and policies leave unset:
+    for instance in instances.iter_mut() {
+        instance
+            .transmission
+            .webhooks
+            .extend(webhooks.iter().cloned());
+        if let Some(defaults) = &defaults {
+            defaults.apply_to(instance);
+        }
+        instance
+            .sanity_check()
+            .map_err(|e| format!("Config {file:?}: {e}"))?;
+    }
+    Ok(instances)
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, JsonSchema, CustomType)]
 #[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
 pub struct Instance {
     pub transmission: Transmission,
     pub policies: Vec<DeletePolicy>,
+    /// The settings that this instance and its policies inherited from
+    /// the config's `defaults()`, like `transmission.poll_interval` or
+    /// `policies.<name>.match.max_ratio`. Only loading a config fills
+    /// this in; it's never read from or written to config files.
+    #[rhai_type(skip)]
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub inherited: BTreeSet<String>,
 }
 
 impl Instance {
@@ -46,12 +163,49 @@ impl Instance {
         builder.with_fn("rules", Self::new);
     }
 
+    /// The name that identifies this instance in logs, metrics and the
+    /// API: either the one it was given, or its URL.
+    pub fn name(&self) -> &str {
+        self.transmission
+            .name
+            .as_deref()
+            .unwrap_or(&self.transmission.url)
+    }
+
     pub fn new(transmission: Transmission, policies: Array) -> Result<Self, Box<EvalAltResult>> {
         Ok(Instance {
             transmission,
             policies: Dynamic::from(policies)
                 .into_typed_array()
                 .map_err(|e| e.to_string())?,
+            inherited: BTreeSet::new(),
         })
     }
+
+    /// Returns true if the config keeps `policy` from deleting
+    /// anything, even when running with `-f`.
+    pub fn is_dry_run(&self, policy: &DeletePolicy) -> bool {
+        policy
+            .dry_run
+            .or(self.transmission.dry_run)
+            .unwrap_or(false)
+    }
+
+    /// Ensures that the instance's settings are usable, and that none
+    /// of its policies would delete every torrent it governs.
+    pub fn sanity_check(&self) -> Result<(), String> {
+        self.transmission
+            .sanity_check()
+            .map_err(|e| format!("Instance {:?}: {e}", self.name()))?;
+        for (index, policy) in self.policies.iter().enumerate() {
+            policy.match_when.sanity_check().map_err(|e| {
+                format!(
+                    "Policy {:?} on instance {:?}: {e}",
+                    policy.name_or_index(index),
+                    self.name()
+                )
+            })?;
+        }
+        Ok(())
+    }
 }
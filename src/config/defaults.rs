@@ -0,0 +1,284 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use super::limits::DeletionLimits;
+use super::lint::warn_if_overridden;
+use super::policy::{Condition, PolicyMatch};
+use super::secret::Secret;
+use super::units::TimeSpan;
+use super::window::ActionWindow;
+use super::Instance;
+use crate::util::chrono_optional_duration;
+use chrono::Duration;
+use rhai::{Array, CustomType, Dynamic, EvalAltResult, TypeBuilder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Settings that every instance and policy in a config inherits,
+/// unless it sets them itself.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema, CustomType)]
+#[rhai_type(extra = Self::build_rhai)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    /// The user to log in as. Instances that set neither a user nor
+    /// a password inherit both.
+    #[rhai_type(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[rhai_type(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<Secret>,
+    #[rhai_type(skip)]
+    #[serde(
+        with = "chrono_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub poll_interval: Option<Duration>,
+    /// Deletion limits for each instance.
+    #[rhai_type(skip)]
+    pub limits: DeletionLimits,
+    /// The action window for each instance.
+    #[rhai_type(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action_window: Option<ActionWindow>,
+    /// Whether instances only report what they would delete.
+    #[rhai_type(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
+    /// The tracker hostnames of each policy that lists none itself.
+    #[rhai_type(skip)]
+    #[serde(skip_serializing_if = "HashSet::is_empty")]
+    pub trackers: HashSet<String>,
+    /// Thresholds for each policy's match condition.
+    #[rhai_type(skip)]
+    #[serde(rename = "match")]
+    pub match_when: Condition,
+}
+
+impl Defaults {
+    fn build_rhai(builder: &mut TypeBuilder<Self>) {
+        builder
+            .with_fn("defaults", Self::default)
+            .with_fn("user", Self::with_user)
+            .with_fn("password", Self::with_password)
+            .with_fn("password_env", Self::with_password_env)
+            .with_fn("password_file", Self::with_password_file)
+            .with_fn("poll_interval", Self::with_poll_interval)
+            .with_fn("max_deletions_per_tick", Self::with_max_deletions_per_tick)
+            .with_fn("max_bytes_per_hour", Self::with_max_bytes_per_hour)
+            .with_fn("action_window", Self::with_action_window)
+            .with_fn("dry_run", Self::with_dry_run)
+            .with_fn("trackers", Self::with_trackers)
+            .with_fn("max_ratio", Self::with_max_ratio)
+            .with_fn("min_seeding_time", Self::with_min_seeding_time)
+            .with_fn("max_seeding_time", Self::with_max_seeding_time);
+    }
+
+    pub fn with_user(mut self, user: &str) -> Self {
+        warn_if_overridden(self.user.is_some(), "user");
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn with_password(mut self, password: &str) -> Self {
+        warn_if_overridden(self.password.is_some(), "password");
+        self.password = Some(Secret::Inline(password.to_string()));
+        self
+    }
+
+    pub fn with_password_env(self, name: &str) -> Result<Self, Box<EvalAltResult>> {
+        self.with_password_secret(Secret::Env {
+            env: name.to_string(),
+        })
+    }
+
+    pub fn with_password_file(self, path: &str) -> Result<Self, Box<EvalAltResult>> {
+        self.with_password_secret(Secret::File { file: path.into() })
+    }
+
+    fn with_password_secret(mut self, secret: Secret) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.password.is_some(), "password");
+        secret.reveal().map_err(|e| format!("{e:#}"))?;
+        self.password = Some(secret);
+        Ok(self)
+    }
+
+    pub fn with_poll_interval(mut self, interval: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.poll_interval.is_some(), "poll_interval");
+        self.poll_interval = Some(TimeSpan::from_rhai(interval)?);
+        Ok(self)
+    }
+
+    pub fn with_max_deletions_per_tick(mut self, max: i64) -> Result<Self, Box<EvalAltResult>> {
+        self.limits = self.limits.with_max_deletions_per_tick(max)?;
+        Ok(self)
+    }
+
+    pub fn with_max_bytes_per_hour(mut self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.limits = self.limits.with_max_bytes_per_hour(max)?;
+        Ok(self)
+    }
+
+    pub fn with_action_window(
+        mut self,
+        window: &str,
+        timezone: &str,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.action_window.is_some(), "action_window");
+        self.action_window = Some(ActionWindow::from_rhai(window, timezone)?);
+        Ok(self)
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        warn_if_overridden(self.dry_run.is_some(), "dry_run");
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    pub fn with_trackers(mut self, trackers: Array) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(!self.trackers.is_empty(), "trackers");
+        self.trackers = PolicyMatch::new(trackers)?.trackers;
+        Ok(self)
+    }
+
+    pub fn with_max_ratio(mut self, max_ratio: f64) -> Self {
+        self.match_when = self.match_when.with_max_ratio(max_ratio);
+        self
+    }
+
+    pub fn with_min_seeding_time(mut self, time: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.match_when = self.match_when.with_min_seeding_time(time)?;
+        Ok(self)
+    }
+
+    pub fn with_max_seeding_time(mut self, time: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.match_when = self.match_when.with_max_seeding_time(time)?;
+        Ok(self)
+    }
+
+    /// Fills in every setting that `instance` and its policies leave
+    /// unset, and records which ones it filled in.
+    pub fn apply_to(&self, instance: &mut Instance) {
+        let mut inherited = BTreeSet::new();
+        let transmission = &mut instance.transmission;
+        if transmission.user.is_none() && transmission.password.is_none() {
+            inherit(
+                &mut transmission.user,
+                &self.user,
+                "transmission.user",
+                &mut inherited,
+            );
+            inherit(
+                &mut transmission.password,
+                &self.password,
+                "transmission.password",
+                &mut inherited,
+            );
+        }
+        inherit(
+            &mut transmission.poll_interval,
+            &self.poll_interval,
+            "transmission.poll_interval",
+            &mut inherited,
+        );
+        inherit(
+            &mut transmission.limits.max_deletions_per_tick,
+            &self.limits.max_deletions_per_tick,
+            "transmission.limits.max_deletions_per_tick",
+            &mut inherited,
+        );
+        inherit(
+            &mut transmission.limits.max_bytes_per_hour,
+            &self.limits.max_bytes_per_hour,
+            "transmission.limits.max_bytes_per_hour",
+            &mut inherited,
+        );
+        inherit(
+            &mut transmission.action_window,
+            &self.action_window,
+            "transmission.action_window",
+            &mut inherited,
+        );
+        inherit(
+            &mut transmission.dry_run,
+            &self.dry_run,
+            "transmission.dry_run",
+            &mut inherited,
+        );
+        for (index, policy) in instance.policies.iter_mut().enumerate() {
+            let name = policy.name_or_index(index).to_string();
+            if policy.precondition.trackers.is_empty() && !self.trackers.is_empty() {
+                policy.precondition.trackers.clone_from(&self.trackers);
+                inherited.insert(format!("policies.{name}.precondition.trackers"));
+            }
+            let prefix = format!("policies.{name}.match");
+            let condition = &mut policy.match_when;
+            inherit(
+                &mut condition.max_ratio,
+                &self.match_when.max_ratio,
+                &format!("{prefix}.max_ratio"),
+                &mut inherited,
+            );
+            inherit(
+                &mut condition.min_seeding_time,
+                &self.match_when.min_seeding_time,
+                &format!("{prefix}.min_seeding_time"),
+                &mut inherited,
+            );
+            inherit(
+                &mut condition.max_seeding_time,
+                &self.match_when.max_seeding_time,
+                &format!("{prefix}.max_seeding_time"),
+                &mut inherited,
+            );
+        }
+        instance.inherited.extend(inherited);
+    }
+}
+
+fn inherit<T: Clone>(
+    value: &mut Option<T>,
+    default: &Option<T>,
+    key: &str,
+    inherited: &mut BTreeSet<String>,
+) {
+    if value.is_none() && default.is_some() {
+        value.clone_from(default);
+        inherited.insert(key.to_string());
+    }
+}
+
+impl fmt::Debug for Defaults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Defaults({self})")
+    }
+}
+
+impl fmt::Display for Defaults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.user, &self.password) {
+            (Some(user), Some(_password)) => write!(f, "u:{user}:***")?,
+            (Some(user), None) => write!(f, "u:{user}")?,
+            (None, _) => write!(f, "-")?,
+        }
+        if let Some(interval) = self.poll_interval {
+            write!(f, ", every {interval}")?;
+        }
+        if !self.limits.is_unlimited() {
+            write!(f, ", {}", self.limits)?;
+        }
+        if let Some(window) = &self.action_window {
+            write!(f, ", window:{window}")?;
+        }
+        if let Some(dry_run) = self.dry_run {
+            write!(f, ", dry_run:{dry_run}")?;
+        }
+        if !self.trackers.is_empty() {
+            let mut trackers: Vec<_> = self.trackers.iter().collect();
+            trackers.sort();
+            write!(f, ", trackers:{trackers:?}")?;
+        }
+        write!(f, ", {}", self.match_when)
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::defaults::Defaults;
+use super::webhook::Webhook;
+use super::Instance;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// The languages that config files can be written in.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ConfigFormat {
+    Rhai,
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Guesses the format from the file extension. Anything that
+    /// isn't recognizably TOML, YAML or JSON is taken to be Rhai.
+    pub fn from_path(file: &Path) -> Self {
+        match file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Rhai,
+        }
+    }
+
+    /// Parses a declarative config. Rhai configs need evaluating
+    /// instead, see [`super::configure`].
+    pub(crate) fn parse(&self, contents: &str) -> Result<DeclarativeConfig, String> {
+        match self {
+            ConfigFormat::Rhai => Err("Rhai configs can't be parsed as data".to_string()),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml_ng::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rhai" => Ok(ConfigFormat::Rhai),
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            other => Err(format!(
+                "Unknown config format {other:?}, expected rhai, toml, yaml or json"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Rhai => write!(f, "rhai"),
+            ConfigFormat::Toml => write!(f, "toml"),
+            ConfigFormat::Yaml => write!(f, "yaml"),
+            ConfigFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// A config written down as data: the entries of a Rhai config's
+/// top-level list, sorted into fields by kind.
+#[derive(PartialEq, Clone, Default, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeclarativeConfig {
+    /// Settings that instances and policies inherit unless they set
+    /// them themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<Defaults>,
+
+    /// Webhooks that get notified about every instance.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+
+    /// The transmission instances to maintain, and their policies.
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+}
+
+impl DeclarativeConfig {
+    /// A config that loads back to exactly `instances`, which have
+    /// already been resolved: defaults and top-level webhooks are
+    /// part of each instance.
+    pub fn from_instances(instances: Vec<Instance>) -> Self {
+        DeclarativeConfig {
+            defaults: None,
+            webhooks: vec![],
+            instances,
+        }
+    }
+
+    /// Writes the config in `format`.
+    pub fn render(&self, format: ConfigFormat) -> Result<String, String> {
+        match format {
+            ConfigFormat::Rhai => Err("Configs can't be exported to Rhai".to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml_ng::to_string(self).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(self)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The JSON Schema for TOML, YAML and JSON configs.
+pub fn json_schema() -> RootSchema {
+    schema_for!(DeclarativeConfig)
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use rhai::{Dynamic, EvalAltResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::lint::warn_if_overridden;
+use super::units::ByteSize;
+
+/// Caps on how much may be deleted, either on a whole transmission
+/// instance or by a single policy.
+///
+/// Candidates that exceed a limit aren't dropped; they get deferred
+/// to a later tick.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeletionLimits {
+    /// The maximum number of torrents that may be deleted in a single tick.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deletions_per_tick: Option<usize>,
+
+    /// The maximum number of bytes that may be deleted within any
+    /// one-hour window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes_per_hour: Option<u64>,
+}
+
+impl DeletionLimits {
+    pub(crate) fn with_max_deletions_per_tick(self, max: i64) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(
+            self.max_deletions_per_tick.is_some(),
+            "max_deletions_per_tick",
+        );
+        let max = usize::try_from(max)
+            .map_err(|_| format!("max_deletions_per_tick must not be negative, got {max}"))?;
+        Ok(Self {
+            max_deletions_per_tick: Some(max),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_max_bytes_per_hour(self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.max_bytes_per_hour.is_some(), "max_bytes_per_hour");
+        Ok(Self {
+            max_bytes_per_hour: Some(ByteSize::from_rhai(max)?),
+            ..self
+        })
+    }
+
+    /// Returns true if no limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_deletions_per_tick.is_none() && self.max_bytes_per_hour.is_none()
+    }
+}
+
+impl fmt::Display for DeletionLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Limits:[")?;
+        if let Some(max) = self.max_deletions_per_tick {
+            write!(f, " n<={max}/tick")?;
+        }
+        if let Some(max) = self.max_bytes_per_hour {
+            write!(f, " b<={max}/h")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Settings for the safety circuit breaker, which refuses to delete
+/// anything on an instance when a tick looks like it would remove
+/// far more than usual.
+#[derive(PartialEq, Clone, Copy, Default, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreaker {
+    /// The largest fraction (between 0 and 1) of an instance's
+    /// torrents, by count or by size, that a single tick may delete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deletion_fraction: Option<f64>,
+
+    /// The smallest number of torrents that the instance must report
+    /// for any deletions to happen. If unset but
+    /// [`max_deletion_fraction`] is, an empty torrent list trips the
+    /// breaker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_torrent_count: Option<usize>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn with_max_deletion_fraction(
+        self,
+        fraction: f64,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(
+            self.max_deletion_fraction.is_some(),
+            "max_deletion_fraction",
+        );
+        if !(0.0..=1.0).contains(&fraction) {
+            Err(format!(
+                "max_deletion_fraction must be between 0.0 and 1.0, got {fraction}"
+            ))?;
+        }
+        Ok(Self {
+            max_deletion_fraction: Some(fraction),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_min_torrent_count(self, min: i64) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.min_torrent_count.is_some(), "min_torrent_count");
+        let min = usize::try_from(min)
+            .map_err(|_| format!("min_torrent_count must not be negative, got {min}"))?;
+        Ok(Self {
+            min_torrent_count: Some(min),
+            ..self
+        })
+    }
+
+    /// Returns true if the circuit breaker has any settings.
+    pub fn is_enabled(&self) -> bool {
+        self.max_deletion_fraction.is_some() || self.min_torrent_count.is_some()
+    }
+}
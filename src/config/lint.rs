@@ -0,0 +1,397 @@
+//! Finds policies that can't work the way they were meant to: ones
+//! that contradict themselves, can never apply, or compete with
+//! other policies for the same torrents.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::policy::{Condition, DeletePolicy, PolicyMatch};
+use super::units::TimeSpan;
+use super::Instance;
+use tracing::warn;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Severity {
+    /// Probably a mistake, but the policy still does something.
+    Warning,
+
+    /// The policy can't do what it says.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem with one or more policies on an instance.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub instance: String,
+    /// The names of the policies involved.
+    pub policies: Vec<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policies: Vec<_> = self.policies.iter().map(|p| format!("{p:?}")).collect();
+        write!(
+            f,
+            "{}: {}: {} {}: {}",
+            self.severity,
+            self.instance,
+            if policies.len() == 1 {
+                "policy"
+            } else {
+                "policies"
+            },
+            policies.join(" and "),
+            self.message
+        )
+    }
+}
+
+/// Checks the policies on every instance, returning the problems
+/// found, errors first.
+pub fn lint(instances: &[Instance]) -> Vec<Finding> {
+    let mut findings = vec![];
+    for instance in instances {
+        let policies: Vec<_> = instance
+            .policies
+            .iter()
+            .enumerate()
+            .map(|(index, policy)| (policy.name_or_index(index).into_owned(), policy))
+            .collect();
+        let mut report = |severity, policies: &[&str], message: String| {
+            findings.push(Finding {
+                severity,
+                instance: instance.name().to_string(),
+                policies: policies.iter().map(|p| p.to_string()).collect(),
+                message,
+            })
+        };
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (name, policy) in policies.iter() {
+            *counts.entry(name).or_default() += 1;
+            for (severity, message) in lint_policy(policy) {
+                report(severity, &[name], message);
+            }
+        }
+        for (name, count) in counts {
+            if count > 1 {
+                report(
+                    Severity::Warning,
+                    &[name],
+                    format!("{count} policies have this name, so their metrics and history get mixed up"),
+                );
+            }
+        }
+
+        for (i, (name, policy)) in policies.iter().enumerate() {
+            for (other_name, other) in policies.iter().skip(i + 1) {
+                if let Some((severity, message)) =
+                    lint_overlap(&policy.precondition, &other.precondition)
+                {
+                    report(severity, &[name, other_name], message);
+                }
+            }
+        }
+    }
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    findings
+}
+
+/// Problems with a policy on its own.
+fn lint_policy(policy: &DeletePolicy) -> Vec<(Severity, String)> {
+    let mut findings = vec![];
+    let PolicyMatch {
+        trackers,
+        min_file_count,
+        max_file_count,
+    } = &policy.precondition;
+    if trackers.is_empty() {
+        findings.push((
+            Severity::Error,
+            "on_trackers lists no trackers, so the policy never applies".to_string(),
+        ));
+    }
+    match (min_file_count, max_file_count) {
+        (Some(min), Some(max)) if min > max => findings.push((
+            Severity::Error,
+            format!("min_file_count ({min}) is greater than max_file_count ({max}), so the policy never applies"),
+        )),
+        (_, Some(max)) if *max < 1 => findings.push((
+            Severity::Error,
+            format!("max_file_count is {max}, but every torrent has at least one file"),
+        )),
+        _ => {}
+    }
+
+    let Condition {
+        max_ratio,
+        min_seeding_time,
+        max_seeding_time,
+    } = &policy.match_when;
+    match (min_seeding_time, max_seeding_time, max_ratio) {
+        (Some(min), Some(max), _) if min > max => findings.push((
+            Severity::Error,
+            format!(
+                "min_seeding_time ({}) is longer than max_seeding_time ({}), so torrents only get deleted after {}",
+                TimeSpan(*min),
+                TimeSpan(*max),
+                TimeSpan(*min)
+            ),
+        )),
+        (Some(_), None, None) => findings.push((
+            Severity::Error,
+            "only min_seeding_time is set, which never matches on its own; add max_ratio or max_seeding_time".to_string(),
+        )),
+        _ => {}
+    }
+    if let Some(ratio) = max_ratio.filter(|ratio| *ratio <= 0.0) {
+        findings.push((
+            Severity::Warning,
+            format!("max_ratio is {ratio}, which every torrent reaches"),
+        ));
+    }
+    findings
+}
+
+/// Problems with two policies that govern some of the same torrents.
+fn lint_overlap(a: &PolicyMatch, b: &PolicyMatch) -> Option<(Severity, String)> {
+    if a == b && !a.trackers.is_empty() {
+        return Some((
+            Severity::Error,
+            "govern exactly the same torrents".to_string(),
+        ));
+    }
+    let mut trackers: Vec<_> = a.trackers.intersection(&b.trackers).cloned().collect();
+    let files = |m: &PolicyMatch| {
+        (
+            m.min_file_count.unwrap_or(1).max(1),
+            m.max_file_count.unwrap_or(i64::MAX),
+        )
+    };
+    let ((a_min, a_max), (b_min, b_max)) = (files(a), files(b));
+    let (min, max) = (a_min.max(b_min), a_max.min(b_max));
+    if trackers.is_empty() || min > max {
+        return None;
+    }
+    trackers.sort();
+    let files = match (min, max) {
+        (min, i64::MAX) => format!("at least {min} files"),
+        (min, max) if min == max => format!("{min} files"),
+        (min, max) => format!("{min} to {max} files"),
+    };
+    Some((
+        Severity::Warning,
+        format!(
+            "both govern torrents on {} with {files}, and either may delete them",
+            trackers.join(", ")
+        ),
+    ))
+}
+
+/// Warns about a builder setting that replaces the value an earlier
+/// call set, which is almost always a mistake. Settings that have a
+/// default rather than being unset count as set once they differ
+/// from their default.
+pub(crate) fn warn_if_overridden(already_set: bool, setting: &str) {
+    if already_set {
+        warn!(
+            setting,
+            "{setting} is set more than once; only the last value counts"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::transmission::Transmission;
+    use crate::config::webhook::Webhook;
+    use rhai::{Array, Dynamic};
+    use test_case::test_case;
+
+    fn policy(trackers: &[&str], min_files: Option<i64>, max_files: Option<i64>) -> DeletePolicy {
+        let trackers: Array = trackers
+            .iter()
+            .map(|t| Dynamic::from(t.to_string()))
+            .collect();
+        let mut precondition = PolicyMatch::new(trackers).unwrap();
+        precondition.min_file_count = min_files;
+        precondition.max_file_count = max_files;
+        DeletePolicy::new_real(
+            "p",
+            precondition,
+            Condition::new().unwrap().with_max_ratio(1.0),
+        )
+        .unwrap()
+    }
+
+    fn instance(policies: Vec<DeletePolicy>) -> Instance {
+        Instance {
+            transmission: Transmission::new("http://seedbox"),
+            policies: policies
+                .into_iter()
+                .enumerate()
+                .map(|(index, policy)| DeletePolicy {
+                    name: Some(format!("p{index}")),
+                    ..policy
+                })
+                .collect(),
+            inherited: Default::default(),
+        }
+    }
+
+    fn messages(policies: Vec<DeletePolicy>) -> Vec<String> {
+        lint(&[instance(policies)])
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Returns everything that `build` logged.
+    fn logged(build: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, build);
+        let logged = buffer.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[test_case("min_file_count", || {
+        PolicyMatch::default().with_min_file_count(1).with_min_file_count(2);
+    }; "policy")]
+    #[test_case("insecure_skip_verify", || {
+        Transmission::new("x").with_insecure_skip_verify(true).with_insecure_skip_verify(false);
+    }; "tls")]
+    #[test_case("rpc_timeout", || {
+        Transmission::new("x")
+            .with_rpc_timeout("10s".into()).unwrap()
+            .with_rpc_timeout("20s".into()).unwrap();
+    }; "rpc")]
+    #[test_case("retries", || {
+        Webhook::new("http://hook").with_retries(1).unwrap().with_retries(2).unwrap();
+    }; "webhook")]
+    fn warns_about_overridden_settings(setting: &str, build: fn()) {
+        let logged = logged(build);
+        assert!(
+            logged.contains(&format!("{setting} is set more than once")),
+            "{logged}"
+        );
+    }
+
+    #[test]
+    fn doesnt_warn_about_settings_set_once() {
+        let logged = logged(|| {
+            Webhook::new("http://hook")
+                .with_include_dry_run(true)
+                .with_timeout("3s".into())
+                .unwrap();
+        });
+        assert_eq!(logged, "");
+    }
+
+    #[test]
+    fn clean_policies() {
+        assert_eq!(
+            messages(vec![
+                policy(&["tracker.horse"], Some(2), None),
+                policy(&["tracker.horse"], None, Some(1)),
+                policy(&["other.horse"], None, None),
+            ]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test_case(&[], None, None, "error: http://seedbox: policy \"p0\": on_trackers lists no trackers, so the policy never applies"; "no trackers")]
+    #[test_case(&["t"], Some(5), Some(2), "error: http://seedbox: policy \"p0\": min_file_count (5) is greater than max_file_count (2), so the policy never applies"; "file counts")]
+    #[test_case(&["t"], None, Some(0), "error: http://seedbox: policy \"p0\": max_file_count is 0, but every torrent has at least one file"; "no files")]
+    fn unreachable(trackers: &[&str], min: Option<i64>, max: Option<i64>, expected: &str) {
+        assert_eq!(messages(vec![policy(trackers, min, max)]), vec![expected]);
+    }
+
+    #[test_case("14 days", "3 days", None, "min_seeding_time (14d) is longer than max_seeding_time (3d), so torrents only get deleted after 14d"; "seeding times")]
+    #[test_case("2 days", "", None, "only min_seeding_time is set, which never matches on its own; add max_ratio or max_seeding_time"; "only min")]
+    fn contradictory(min: &str, max: &str, ratio: Option<f64>, expected: &str) {
+        let mut p = policy(&["t"], None, None);
+        p.match_when = Condition {
+            max_ratio: ratio,
+            min_seeding_time: Some(crate::util::parse_duration(min).unwrap()),
+            max_seeding_time: (!max.is_empty()).then(|| crate::util::parse_duration(max).unwrap()),
+        };
+        assert_eq!(
+            messages(vec![p]),
+            vec![format!("error: http://seedbox: policy \"p0\": {expected}")]
+        );
+    }
+
+    #[test]
+    fn identical_governance() {
+        assert_eq!(
+            messages(vec![
+                policy(&["t"], Some(2), None),
+                policy(&["t"], Some(2), None)
+            ]),
+            vec!["error: http://seedbox: policies \"p0\" and \"p1\": govern exactly the same torrents"]
+        );
+    }
+
+    #[test_case(&["a", "b"], None, None, &["b", "c"], Some(3), Some(10), Some("both govern torrents on b with 3 to 10 files"); "partial")]
+    #[test_case(&["a"], Some(2), None, &["a"], None, Some(2), Some("both govern torrents on a with 2 files"); "single file count")]
+    #[test_case(&["a"], Some(2), None, &["a"], None, Some(1), None; "disjoint file counts")]
+    #[test_case(&["a"], None, None, &["b"], None, None, None; "disjoint trackers")]
+    fn overlaps(
+        a: &[&str],
+        a_min: Option<i64>,
+        a_max: Option<i64>,
+        b: &[&str],
+        b_min: Option<i64>,
+        b_max: Option<i64>,
+        expected: Option<&str>,
+    ) {
+        let expected: Vec<String> = expected
+            .map(|e| format!("warning: http://seedbox: policies \"p0\" and \"p1\": {e}, and either may delete them"))
+            .into_iter()
+            .collect();
+        assert_eq!(
+            messages(vec![policy(a, a_min, a_max), policy(b, b_min, b_max)]),
+            expected
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let mut policies = instance(vec![policy(&["a"], None, None), policy(&["b"], None, None)]);
+        policies.policies[1].name = Some("p0".to_string());
+        assert_eq!(
+            lint(&[policies])
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["warning: http://seedbox: policy \"p0\": 2 policies have this name, so their metrics and history get mixed up"]
+        );
+    }
+}
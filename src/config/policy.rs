@@ -1,8 +1,13 @@
 use std::{borrow::Cow, collections::HashSet, fmt};
 
+use super::limits::DeletionLimits;
+use super::lint::warn_if_overridden;
+use super::units::TimeSpan;
+use super::window::ActionWindow;
 use crate::util::chrono_optional_duration;
 use chrono::{Duration, Utc};
 use rhai::{Array, CustomType, Dynamic, EvalAltResult, TypeBuilder};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 use transmission_rpc::types::TorrentStatus;
@@ -14,22 +19,27 @@ use crate::Torrent;
 ///
 /// The policy itself doesn't need to match, this is just to indicate
 /// that it *could* even match.
-#[derive(PartialEq, Eq, Clone, Default, Debug, Serialize, Deserialize, CustomType)]
+#[derive(PartialEq, Eq, Clone, Default, Debug, Serialize, Deserialize, JsonSchema, CustomType)]
 #[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
 pub struct PolicyMatch {
     /// The tracker URL hostnames (only the host, not the path or
-    /// port) that the policy should apply to.
+    /// port) that the policy should apply to. If empty, the policy
+    /// inherits the trackers from the defaults.
     #[rhai_type(readonly)]
+    #[serde(default)]
     pub trackers: HashSet<String>,
 
     /// The number of files that must be present in a torrent for the
     /// policy to match. If None, any number of files matches.
     #[rhai_type(readonly)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub min_file_count: Option<i64>,
 
     /// The maximum number of files that may be present in a torrent
     /// for the policy to match. If None, any number of files matches.
     #[rhai_type(readonly)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_file_count: Option<i64>,
 }
 
@@ -50,6 +60,7 @@ impl PolicyMatch {
     }
 
     pub fn with_min_file_count(self, min_file_count: i64) -> Self {
+        warn_if_overridden(self.min_file_count.is_some(), "min_file_count");
         Self {
             min_file_count: Some(min_file_count),
             ..self
@@ -57,6 +68,7 @@ impl PolicyMatch {
     }
 
     pub fn with_max_file_count(self, max_file_count: i64) -> Self {
+        warn_if_overridden(self.max_file_count.is_some(), "max_file_count");
         Self {
             max_file_count: Some(max_file_count),
             ..self
@@ -120,11 +132,13 @@ impl fmt::Display for PolicyMatch {
 /// a transmission instance.
 ///
 /// There's a second set of conditions that need to match: See [PolicyMatch].
-#[derive(PartialEq, Clone, Default, Serialize, Deserialize, CustomType)]
+#[derive(PartialEq, Clone, Default, Serialize, Deserialize, JsonSchema, CustomType)]
 #[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
 pub struct Condition {
     /// The ratio at which a torrent qualifies for deletion, even if
     /// it has been seeded for less than [`max_seeding_time`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_ratio: Option<f64>,
 
     /// The minimum amount of time that a torrent must have been
@@ -132,11 +146,21 @@ pub struct Condition {
     ///
     /// Even if the [`max_ratio`] requirement isn't met, the torrent
     /// won't be deleted unless it's been seeding this long.
-    #[serde(with = "chrono_optional_duration")]
+    #[serde(
+        with = "chrono_optional_duration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
     pub min_seeding_time: Option<Duration>,
 
     /// The duration at which a torrent qualifies for deletion.
-    #[serde(with = "chrono_optional_duration")]
+    #[serde(
+        with = "chrono_optional_duration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
     pub max_seeding_time: Option<Duration>,
 }
 
@@ -155,33 +179,30 @@ impl Condition {
         })
     }
 
-    pub fn with_min_seeding_time(self, min_seeding_time: &str) -> Result<Self, Box<EvalAltResult>> {
-        let min_seeding_time = Some(
-            Duration::from_std(
-                parse_duration::parse(min_seeding_time).map_err(|e| format!("{e}"))?,
-            )
-            .map_err(|e| format!("{e}"))?,
-        );
+    pub fn with_min_seeding_time(
+        self,
+        min_seeding_time: Dynamic,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.min_seeding_time.is_some(), "min_seeding_time");
         Ok(Self {
-            min_seeding_time,
+            min_seeding_time: Some(TimeSpan::from_rhai(min_seeding_time)?),
             ..self
         })
     }
 
-    pub fn with_max_seeding_time(self, max_seeding_time: &str) -> Result<Self, Box<EvalAltResult>> {
-        let max_seeding_time = Some(
-            Duration::from_std(
-                parse_duration::parse(max_seeding_time).map_err(|e| format!("{e}"))?,
-            )
-            .map_err(|e| format!("{e}"))?,
-        );
+    pub fn with_max_seeding_time(
+        self,
+        max_seeding_time: Dynamic,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.max_seeding_time.is_some(), "max_seeding_time");
         Ok(Self {
-            max_seeding_time,
+            max_seeding_time: Some(TimeSpan::from_rhai(max_seeding_time)?),
             ..self
         })
     }
 
     pub fn with_max_ratio(self, max_ratio: f64) -> Self {
+        warn_if_overridden(self.max_ratio.is_some(), "max_ratio");
         Self {
             max_ratio: Some(max_ratio),
             ..self
@@ -192,11 +213,14 @@ impl Condition {
 mod condition_match {
     #![allow(clippy::extra_unused_lifetimes)]
 
+    use crate::util::chrono_duration_seconds;
     use chrono::Duration;
     use enum_kinds::EnumKind;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(PartialEq, Copy, Clone, Debug, EnumKind)]
+    #[derive(PartialEq, Copy, Clone, Debug, EnumKind, Serialize, Deserialize)]
     #[enum_kind(ConditionMatchKind)]
+    #[serde(tag = "kind", content = "value")]
     pub enum ConditionMatch {
         /// Preconditions met, but did not match.
         None,
@@ -205,7 +229,7 @@ mod condition_match {
         Ratio(f64),
 
         /// Matches based on seed time
-        SeedTime(Duration),
+        SeedTime(#[serde(with = "chrono_duration_seconds")] Duration),
     }
 }
 pub use condition_match::*;
@@ -233,7 +257,9 @@ impl ConditionMatch {
 }
 
 impl Condition {
-    pub fn sanity_check(self) -> Result<Self, Box<EvalAltResult>> {
+    /// Ensures that the condition doesn't match every torrent. Run on
+    /// the evaluated config, after defaults were applied.
+    pub fn sanity_check(&self) -> Result<(), String> {
         if [
             self.min_seeding_time.map(|_| true),
             self.max_ratio.map(|_| true),
@@ -242,9 +268,9 @@ impl Condition {
         .iter()
         .all(Option::is_none)
         {
-            Err("Set at least one of min_seeding_time, max_seeding_time, max_ratio - otherwise this deletes all torrents matching the tracker immediately.".to_string())?;
+            return Err("Set at least one of min_seeding_time, max_seeding_time, max_ratio - otherwise this deletes all torrents matching the tracker immediately.".to_string());
         }
-        Ok(self)
+        Ok(())
     }
 
     /// Returns true if the condition matches a given torrent.
@@ -338,9 +364,30 @@ impl ApplicableDeletePolicy<'_> {
     }
 }
 
+/// What happens to a torrent that a [DeletePolicy] matched.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Remove the torrent from transmission, but keep its data.
+    Remove,
+
+    /// Remove the torrent and trash its data.
+    RemoveWithData,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Remove => write!(f, "remove"),
+            Action::RemoveWithData => write!(f, "remove_with_data"),
+        }
+    }
+}
+
 /// Specifies a condition for torrents that can be deleted.
-#[derive(PartialEq, Clone, Serialize, Deserialize, CustomType)]
+#[derive(PartialEq, Clone, Serialize, Deserialize, JsonSchema, CustomType)]
 #[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
 pub struct DeletePolicy {
     pub name: Option<String>,
 
@@ -353,13 +400,33 @@ pub struct DeletePolicy {
 
     /// Whether to pass "trash data" to the transmission API method.
     pub delete_data: bool,
+
+    /// Limits on how much this policy may delete.
+    #[rhai_type(skip)]
+    #[serde(default)]
+    pub limits: DeletionLimits,
+
+    /// When this policy may delete torrents; any time if unset.
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_window: Option<ActionWindow>,
+
+    /// Whether this policy only reports what it would delete;
+    /// inherited from the instance if unset.
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
 }
 
 impl DeletePolicy {
     fn build_rhai(builder: &mut TypeBuilder<Self>) {
         builder
             .with_fn("noop_delete_policy", Self::new_noop)
-            .with_fn("delete_policy", Self::new_real);
+            .with_fn("delete_policy", Self::new_real)
+            .with_fn("max_deletions_per_tick", Self::with_max_deletions_per_tick)
+            .with_fn("max_bytes_per_hour", Self::with_max_bytes_per_hour)
+            .with_fn("action_window", Self::with_action_window)
+            .with_fn("dry_run", Self::with_dry_run);
     }
 
     /// Constructs a "no-op" deletion policy that will not delete data if matched.
@@ -371,8 +438,11 @@ impl DeletePolicy {
         Ok(Self {
             name: Some(name.to_string()),
             precondition: apply_when,
-            match_when: match_when.sanity_check()?,
+            match_when,
             delete_data: false,
+            limits: Default::default(),
+            action_window: None,
+            dry_run: None,
         })
     }
 
@@ -385,11 +455,48 @@ impl DeletePolicy {
         Ok(DeletePolicy {
             name: Some(name.to_string()),
             precondition: apply_when,
-            match_when: match_when.sanity_check()?,
+            match_when,
             delete_data: true,
+            limits: Default::default(),
+            action_window: None,
+            dry_run: None,
+        })
+    }
+
+    pub fn with_max_deletions_per_tick(self, max: i64) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self {
+            limits: self.limits.with_max_deletions_per_tick(max)?,
+            ..self
+        })
+    }
+
+    pub fn with_max_bytes_per_hour(self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self {
+            limits: self.limits.with_max_bytes_per_hour(max)?,
+            ..self
         })
     }
 
+    pub fn with_action_window(
+        self,
+        window: &str,
+        timezone: &str,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.action_window.is_some(), "action_window");
+        Ok(Self {
+            action_window: Some(ActionWindow::from_rhai(window, timezone)?),
+            ..self
+        })
+    }
+
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        warn_if_overridden(self.dry_run.is_some(), "dry_run");
+        Self {
+            dry_run: Some(dry_run),
+            ..self
+        }
+    }
+
     /// Ensures that the policy can be applied to a torrent, and only
     /// if it is, allows chaining a `.matches` call.
     pub fn applicable<'a>(&'a self, t: &'a Torrent) -> Option<ApplicableDeletePolicy<'a>> {
@@ -412,14 +519,44 @@ impl fmt::Display for DeletePolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "DeletePolicy:[{:?}, {}, delete_data:{}]",
+            "DeletePolicy:[{:?}, {}, delete_data:{}",
             self.name, self.match_when, self.delete_data
-        )
+        )?;
+        if !self.limits.is_unlimited() {
+            write!(f, ", {}", self.limits)?;
+        }
+        if let Some(window) = &self.action_window {
+            write!(f, ", window:{window}")?;
+        }
+        if let Some(dry_run) = self.dry_run {
+            write!(f, ", dry_run:{dry_run}")?;
+        }
+        write!(f, "]")
     }
 }
 
 impl DeletePolicy {
-    pub fn name_or_index(&self, index: usize) -> Cow<String> {
+    /// The action to take on torrents that this policy matches.
+    pub fn action(&self) -> Action {
+        if self.delete_data {
+            Action::RemoveWithData
+        } else {
+            Action::Remove
+        }
+    }
+
+    /// The condition under which this policy governs a torrent.
+    pub fn precondition(&self) -> &PolicyMatch {
+        &self.precondition
+    }
+
+    /// The condition under which this policy deletes a governed
+    /// torrent.
+    pub fn match_when(&self) -> &Condition {
+        &self.match_when
+    }
+
+    pub fn name_or_index(&self, index: usize) -> Cow<'_, String> {
         self.name
             .as_ref()
             .map(Cow::Borrowed)
@@ -444,7 +581,7 @@ mod test {
     #[test_case("12 days", 1.5, Some(ConditionMatchKind::Ratio); "when seeding long enough at exceeded ratio")]
     #[test_log::test]
     fn condition_seed_time(time: &str, upload_ratio: f32, matches: Option<ConditionMatchKind>) {
-        let time = Duration::from_std(parse_duration::parse(time).unwrap()).unwrap();
+        let time = crate::util::parse_duration(time).unwrap();
         let precondition = PolicyMatch {
             trackers: vec!["tracker".to_string()].into_iter().collect(),
             ..Default::default()
@@ -459,6 +596,9 @@ mod test {
             precondition,
             match_when,
             delete_data: false,
+            limits: Default::default(),
+            action_window: None,
+            dry_run: None,
         };
         let t = Torrent {
             id: 1,
@@ -469,6 +609,7 @@ mod test {
             error_string: "".to_string(),
             upload_ratio,
             computed_upload_ratio: upload_ratio as f64,
+            uploaded_ever: 0,
             status: TorrentStatus::Seeding,
             num_files: 1,
             total_size: 30000,
@@ -504,6 +645,9 @@ mod test {
             precondition,
             name: None,
             delete_data: false,
+            limits: Default::default(),
+            action_window: None,
+            dry_run: None,
         };
         let t = Torrent {
             id: 1,
@@ -514,6 +658,7 @@ mod test {
             error_string: "".to_string(),
             upload_ratio: 2.0,
             computed_upload_ratio: 2.0,
+            uploaded_ever: 0,
             status: TorrentStatus::Seeding,
             num_files,
             total_size: 30000,
@@ -543,13 +688,15 @@ mod test {
             max_ratio: Some(1.0),
             min_seeding_time: Some(Duration::minutes(60)),
             max_seeding_time: Some(Duration::days(2)),
-            ..Default::default()
         };
         let pol = DeletePolicy {
             match_when,
             precondition,
             name: None,
             delete_data: false,
+            limits: Default::default(),
+            action_window: None,
+            dry_run: None,
         };
         let t = Torrent {
             id: 1,
@@ -560,6 +707,7 @@ mod test {
             error_string: "".to_string(),
             upload_ratio: 2.0,
             computed_upload_ratio: 2.0,
+            uploaded_ever: 0,
             status: TorrentStatus::Seeding,
             num_files: 3,
             total_size: 30000,
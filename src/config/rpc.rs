@@ -0,0 +1,147 @@
+use chrono::Duration;
+use rhai::{Dynamic, EvalAltResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::lint::warn_if_overridden;
+use super::units::TimeSpan;
+use crate::util::{chrono_duration, chrono_optional_duration};
+
+pub const DEFAULT_RPC_TIMEOUT_SECS: i64 = 60;
+pub const DEFAULT_RPC_RETRIES: u32 = 2;
+/// Retries back off exponentially, so more than a few of them would
+/// keep a tick waiting for a long time.
+pub const MAX_RPC_RETRIES: u32 = 10;
+pub const DEFAULT_MAX_POLL_BACKOFF_MINS: i64 = 60;
+
+fn default_timeout() -> Duration {
+    Duration::seconds(DEFAULT_RPC_TIMEOUT_SECS)
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_RPC_RETRIES
+}
+
+/// How to deal with slow and failing transmission RPC calls.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RpcSettings {
+    /// How long to wait for each RPC call.
+    #[serde(with = "chrono_duration", default = "default_timeout")]
+    #[schemars(with = "String")]
+    pub timeout: Duration,
+
+    /// How often to retry fetching the list of torrents, at most
+    /// [`MAX_RPC_RETRIES`] times. Removals are never retried, since a
+    /// removal that timed out may still have happened.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+
+    /// The longest that repeated failures may stretch the poll
+    /// interval to. Defaults to an hour, or the poll interval if that
+    /// is longer.
+    #[serde(
+        with = "chrono_optional_duration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub max_poll_backoff: Option<Duration>,
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        RpcSettings {
+            timeout: default_timeout(),
+            retries: default_retries(),
+            max_poll_backoff: None,
+        }
+    }
+}
+
+impl RpcSettings {
+    pub(crate) fn with_timeout(self, timeout: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.timeout != default_timeout(), "rpc_timeout");
+        let timeout = TimeSpan::from_rhai(timeout)?;
+        if timeout.is_zero() {
+            Err("rpc_timeout must be longer than zero")?;
+        }
+        Ok(Self { timeout, ..self })
+    }
+
+    pub(crate) fn with_retries(self, retries: i64) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.retries != default_retries(), "rpc_retries");
+        let retries = u32::try_from(retries)
+            .ok()
+            .filter(|retries| *retries <= MAX_RPC_RETRIES)
+            .ok_or_else(|| {
+                format!("rpc_retries must be between 0 and {MAX_RPC_RETRIES}, got {retries}")
+            })?;
+        Ok(Self { retries, ..self })
+    }
+
+    pub(crate) fn with_max_poll_backoff(self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.max_poll_backoff.is_some(), "max_poll_backoff");
+        Ok(Self {
+            max_poll_backoff: Some(TimeSpan::from_rhai(max)?),
+            ..self
+        })
+    }
+
+    /// How long to wait until the next scheduled tick, after
+    /// `consecutive_failures` ticks in a row failed.
+    ///
+    /// A single failure keeps the regular `poll_interval`; after that,
+    /// the interval doubles with each failure, up to the maximum
+    /// backoff.
+    pub fn poll_delay(&self, poll_interval: Duration, consecutive_failures: u32) -> Duration {
+        let max = self
+            .max_poll_backoff
+            .unwrap_or(Duration::minutes(DEFAULT_MAX_POLL_BACKOFF_MINS))
+            .max(poll_interval);
+        let doublings = consecutive_failures.saturating_sub(1).min(30);
+        poll_interval
+            .checked_mul(1 << doublings)
+            .unwrap_or(max)
+            .min(max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0, 5; "healthy")]
+    #[test_case(1, 5; "one failure")]
+    #[test_case(2, 10; "two failures")]
+    #[test_case(4, 40; "four failures")]
+    #[test_case(5, 60; "capped")]
+    #[test_case(u32::MAX, 60; "many failures")]
+    fn backs_off(failures: u32, minutes: i64) {
+        assert_eq!(
+            RpcSettings::default().poll_delay(Duration::minutes(5), failures),
+            Duration::minutes(minutes)
+        );
+    }
+
+    #[test_case(0, true; "none")]
+    #[test_case(10, true; "maximum")]
+    #[test_case(11, false; "too many")]
+    #[test_case(-1, false; "negative")]
+    #[test_case(i64::MAX, false; "huge")]
+    fn bounded_retries(retries: i64, ok: bool) {
+        assert_eq!(RpcSettings::default().with_retries(retries).is_ok(), ok);
+    }
+
+    #[test]
+    fn never_backs_off_below_poll_interval() {
+        let settings = RpcSettings::default()
+            .with_max_poll_backoff("1 minute".into())
+            .unwrap();
+        assert_eq!(
+            settings.poll_delay(Duration::minutes(5), 3),
+            Duration::minutes(5)
+        );
+    }
+}
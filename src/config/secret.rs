@@ -0,0 +1,83 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A credential, either given inline or looked up each time it is
+/// needed, so that rotated secrets get picked up without a restart.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Secret {
+    Inline(String),
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+impl Secret {
+    /// Returns the secret's current value. Trailing newlines are
+    /// stripped from secrets read from files.
+    pub fn reveal(&self) -> anyhow::Result<String> {
+        match self {
+            Secret::Inline(value) => Ok(value.clone()),
+            Secret::Env { env } => std::env::var(env)
+                .with_context(|| format!("Could not read environment variable {env:?}")),
+            Secret::File { file } => Ok(std::fs::read_to_string(file)
+                .with_context(|| format!("Could not read secret file {file:?}"))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string()),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Inline(_) => write!(f, "***"),
+            Secret::Env { env } => write!(f, "env:{env}"),
+            Secret::File { file } => write!(f, "file:{}", file.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn reveals_file_secrets() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "hunter2").unwrap();
+        let secret = Secret::File {
+            file: file.path().to_owned(),
+        };
+        assert_eq!(secret.reveal().unwrap(), "hunter2");
+
+        std::fs::write(file.path(), "rotated").unwrap();
+        assert_eq!(secret.reveal().unwrap(), "rotated");
+    }
+
+    #[test]
+    fn fails_on_missing_secrets() {
+        assert!(Secret::Env {
+            env: "GEARBOX_MAINTENANCE_TEST_UNSET".to_string()
+        }
+        .reveal()
+        .is_err());
+        assert!(Secret::File {
+            file: "/nonexistent/secret".into()
+        }
+        .reveal()
+        .is_err());
+    }
+
+    #[test]
+    fn redacts_inline_secrets() {
+        assert_eq!(
+            format!("{:?}", Secret::Inline("hunter2".to_string())),
+            "***"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rhai::EvalAltResult;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::lint::warn_if_overridden;
+
+/// A client certificate and its private key, both PEM-encoded.
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCertificate {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+/// How to establish HTTPS connections to a transmission instance.
+///
+/// The files are read again whenever a client gets built, so renewed
+/// certificates get picked up without a restart.
+#[derive(PartialEq, Eq, Clone, Default, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// PEM-encoded CA certificates to trust, in addition to the
+    /// built-in root certificates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// A certificate to authenticate to the server with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<ClientCertificate>,
+
+    /// Accept any server certificate. Only meant for lab setups.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+impl Tls {
+    pub(crate) fn with_ca_bundle(self, path: &str) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.ca_bundle.is_some(), "ca_bundle");
+        Self {
+            ca_bundle: Some(path.into()),
+            ..self
+        }
+        .validated()
+    }
+
+    pub(crate) fn with_client_certificate(
+        self,
+        certificate: &str,
+        key: &str,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.client_certificate.is_some(), "client_certificate");
+        Self {
+            client_certificate: Some(ClientCertificate {
+                certificate: certificate.into(),
+                key: key.into(),
+            }),
+            ..self
+        }
+        .validated()
+    }
+
+    pub(crate) fn with_insecure_skip_verify(self, insecure_skip_verify: bool) -> Self {
+        warn_if_overridden(self.insecure_skip_verify, "insecure_skip_verify");
+        Self {
+            insecure_skip_verify,
+            ..self
+        }
+    }
+
+    fn validated(self) -> Result<Self, Box<EvalAltResult>> {
+        self.http_client().map_err(|e| format!("{e:#}"))?;
+        Ok(self)
+    }
+
+    /// Builds an HTTP client that connects according to these settings.
+    pub fn http_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Could not read CA bundle {path:?}"))?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Could not parse CA bundle {path:?}"))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(ClientCertificate { certificate, key }) = &self.client_certificate {
+            let mut pem = std::fs::read(certificate)
+                .with_context(|| format!("Could not read client certificate {certificate:?}"))?;
+            pem.extend(
+                std::fs::read(key).with_context(|| format!("Could not read client key {key:?}"))?,
+            );
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).with_context(|| {
+                format!("Could not parse client certificate {certificate:?} and key {key:?}")
+            })?);
+        }
+        builder
+            .danger_accept_invalid_certs(self.insecure_skip_verify)
+            .build()
+            .context("Could not build HTTP client")
+    }
+}
+
+impl fmt::Display for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TLS:[")?;
+        if let Some(path) = &self.ca_bundle {
+            write!(f, " ca:{}", path.display())?;
+        }
+        if let Some(client) = &self.client_certificate {
+            write!(f, " cert:{}", client.certificate.display())?;
+        }
+        if self.insecure_skip_verify {
+            write!(f, " insecure")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    struct Pki {
+        dir: tempfile::TempDir,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = params.self_signed(&ca_key).unwrap();
+            let pki = Pki {
+                dir: tempfile::tempdir().unwrap(),
+                ca,
+                ca_key,
+            };
+            std::fs::write(pki.path("ca.pem"), pki.ca.pem()).unwrap();
+            pki
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).to_str().unwrap().to_string()
+        }
+
+        /// Issues a certificate, writing it and its key to `<name>.pem`
+        /// and `<name>.key`.
+        fn issue(
+            &self,
+            name: &str,
+            purpose: ExtendedKeyUsagePurpose,
+        ) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let certificate = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            std::fs::write(self.path(&format!("{name}.pem")), certificate.pem()).unwrap();
+            std::fs::write(self.path(&format!("{name}.key")), key.serialize_pem()).unwrap();
+            (
+                certificate.der().clone(),
+                PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+        }
+
+        /// Starts an HTTPS server that requires a client certificate
+        /// issued by the CA, returning its URL.
+        async fn stand_in(&self) -> String {
+            let (certificate, key) = self.issue("server", ExtendedKeyUsagePurpose::ServerAuth);
+            let provider = Arc::new(default_provider());
+            let mut roots = RootCertStore::empty();
+            roots.add(self.ca.der().clone()).unwrap();
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone())
+                    .build()
+                    .unwrap();
+            let config = ServerConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(vec![certificate], key)
+                .unwrap();
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let Ok(mut stream) = acceptor.accept(stream).await else {
+                            return;
+                        };
+                        let mut request = vec![0; 4096];
+                        let _ = stream.read(&mut request).await;
+                        let _ = stream
+                            .write_all(
+                                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                            )
+                            .await;
+                        let _ = stream.shutdown().await;
+                    });
+                }
+            });
+            format!("https://localhost:{port}/")
+        }
+    }
+
+    async fn fetch(tls: &Tls, url: &str) -> anyhow::Result<String> {
+        Ok(tls.http_client()?.get(url).send().await?.text().await?)
+    }
+
+    #[tokio::test]
+    async fn connects_with_private_ca_and_client_certificate() {
+        let pki = Pki::new();
+        let url = pki.stand_in().await;
+        pki.issue("client", ExtendedKeyUsagePurpose::ClientAuth);
+        let client_certificate = |tls: Tls| {
+            tls.with_client_certificate(&pki.path("client.pem"), &pki.path("client.key"))
+                .unwrap()
+        };
+
+        let trusted = Tls::default().with_ca_bundle(&pki.path("ca.pem")).unwrap();
+        assert_eq!(
+            fetch(&client_certificate(trusted.clone()), &url)
+                .await
+                .unwrap(),
+            "ok"
+        );
+        assert!(
+            fetch(&trusted, &url).await.is_err(),
+            "server must require a client certificate"
+        );
+        assert!(
+            fetch(&client_certificate(Tls::default()), &url)
+                .await
+                .is_err(),
+            "server certificate must not be trusted without the CA"
+        );
+        let insecure = client_certificate(Tls::default().with_insecure_skip_verify(true));
+        assert_eq!(fetch(&insecure, &url).await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn rejects_unreadable_files() {
+        assert!(Tls::default()
+            .with_ca_bundle("/nonexistent/ca.pem")
+            .is_err());
+        assert!(Tls::default()
+            .with_client_certificate("/nonexistent/cert.pem", "/nonexistent/key.pem")
+            .is_err());
+    }
+}
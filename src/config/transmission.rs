@@ -1,63 +1,300 @@
 use std::fmt;
 
-use crate::util::chrono_duration;
+use super::limits::{CircuitBreaker, DeletionLimits};
+use super::lint::warn_if_overridden;
+use super::rpc::{RpcSettings, MAX_RPC_RETRIES};
+use super::secret::Secret;
+use super::tls::Tls;
+use super::units::{ByteSize, TimeSpan};
+use super::webhook::Webhook;
+use super::window::ActionWindow;
+use crate::util::chrono_optional_duration;
 use chrono::Duration;
-use rhai::{CustomType, EvalAltResult, TypeBuilder};
+use rhai::{CustomType, Dynamic, EvalAltResult, TypeBuilder};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub const DEFAULT_POLL_INTERVAL_MINS: i64 = 5;
 
 /// A transmission instance
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, CustomType)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, JsonSchema, CustomType)]
 #[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
 pub struct Transmission {
     #[rhai_type(readonly)]
     pub url: String,
+    /// The name that identifies this instance in logs, metrics and
+    /// the API. Defaults to the URL.
     #[rhai_type(readonly)]
-    pub user: Option<String>,
-    #[rhai_type(readonly)]
-    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     #[rhai_type(readonly)]
-    #[serde(with = "chrono_duration")]
-    pub poll_interval: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<Secret>,
+    /// How often to check on the instance. See
+    /// [`Transmission::poll_interval`] for the effective value.
+    #[rhai_type(skip)]
+    #[serde(
+        with = "chrono_optional_duration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub poll_interval: Option<Duration>,
+    #[rhai_type(skip)]
+    #[serde(default)]
+    pub limits: DeletionLimits,
+    #[rhai_type(skip)]
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreaker,
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+    #[rhai_type(skip)]
+    #[serde(default)]
+    pub tls: Tls,
+    #[rhai_type(skip)]
+    #[serde(default)]
+    pub rpc: RpcSettings,
+    /// When torrents on this instance may be deleted; any time if unset.
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_window: Option<ActionWindow>,
+    /// Log a warning when torrents that no policy governs take up
+    /// more than this many bytes.
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn_unmanaged_bytes: Option<u64>,
+    /// Whether policies on this instance only report what they would
+    /// delete, unless they set `dry_run` themselves.
+    #[rhai_type(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
 }
 
 impl Transmission {
     fn build_rhai(builder: &mut TypeBuilder<Self>) {
         builder
             .with_fn("transmission", Self::new)
+            .with_fn("name", Self::with_name)
             .with_fn("user", Self::with_user)
             .with_fn("password", Self::with_password)
-            .with_fn("poll_interval", Self::with_poll_interval);
+            .with_fn("password_env", Self::with_password_env)
+            .with_fn("password_file", Self::with_password_file)
+            .with_fn("poll_interval", Self::with_poll_interval)
+            .with_fn("max_deletions_per_tick", Self::with_max_deletions_per_tick)
+            .with_fn("max_bytes_per_hour", Self::with_max_bytes_per_hour)
+            .with_fn("max_deletion_fraction", Self::with_max_deletion_fraction)
+            .with_fn("min_torrent_count", Self::with_min_torrent_count)
+            .with_fn("notify_webhook", Self::with_webhook)
+            .with_fn("notify_webhook", Self::with_webhook_url)
+            .with_fn("warn_unmanaged_bytes", Self::with_warn_unmanaged_bytes)
+            .with_fn("ca_bundle", Self::with_ca_bundle)
+            .with_fn("client_certificate", Self::with_client_certificate)
+            .with_fn("insecure_skip_verify", Self::with_insecure_skip_verify)
+            .with_fn("rpc_timeout", Self::with_rpc_timeout)
+            .with_fn("rpc_retries", Self::with_rpc_retries)
+            .with_fn("max_poll_backoff", Self::with_max_poll_backoff)
+            .with_fn("action_window", Self::with_action_window)
+            .with_fn("dry_run", Self::with_dry_run);
     }
 
     pub fn new(url: &str) -> Self {
         Self {
             url: url.to_string(),
+            name: None,
             user: None,
             password: None,
-            poll_interval: Duration::minutes(DEFAULT_POLL_INTERVAL_MINS),
+            poll_interval: None,
+            limits: Default::default(),
+            circuit_breaker: Default::default(),
+            webhooks: vec![],
+            tls: Default::default(),
+            rpc: Default::default(),
+            action_window: None,
+            warn_unmanaged_bytes: None,
+            dry_run: None,
         }
     }
 
+    pub fn with_name(mut self, name: &str) -> Self {
+        warn_if_overridden(self.name.is_some(), "name");
+        self.name = Some(name.to_string());
+        self
+    }
+
     pub fn with_user(mut self, user: &str) -> Self {
+        warn_if_overridden(self.user.is_some(), "user");
         self.user = Some(user.to_string());
         self
     }
 
     pub fn with_password(mut self, password: &str) -> Self {
-        self.password = Some(password.to_string());
+        warn_if_overridden(self.password.is_some(), "password");
+        self.password = Some(Secret::Inline(password.to_string()));
+        self
+    }
+
+    /// Reads the password from the environment variable `name`
+    /// whenever connecting.
+    pub fn with_password_env(self, name: &str) -> Result<Self, Box<EvalAltResult>> {
+        self.with_password_secret(Secret::Env {
+            env: name.to_string(),
+        })
+    }
+
+    /// Reads the password from the file at `path` whenever connecting.
+    pub fn with_password_file(self, path: &str) -> Result<Self, Box<EvalAltResult>> {
+        self.with_password_secret(Secret::File { file: path.into() })
+    }
+
+    fn with_password_secret(mut self, secret: Secret) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.password.is_some(), "password");
+        secret.reveal().map_err(|e| format!("{e:#}"))?;
+        self.password = Some(secret);
+        Ok(self)
+    }
+
+    pub fn with_poll_interval(mut self, interval: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.poll_interval.is_some(), "poll_interval");
+        self.poll_interval = Some(TimeSpan::from_rhai(interval)?);
+        Ok(self)
+    }
+
+    /// How often to check on the instance: either the configured
+    /// interval, or every [`DEFAULT_POLL_INTERVAL_MINS`] minutes.
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+            .unwrap_or(Duration::minutes(DEFAULT_POLL_INTERVAL_MINS))
+    }
+
+    pub fn with_max_deletions_per_tick(mut self, max: i64) -> Result<Self, Box<EvalAltResult>> {
+        self.limits = self.limits.with_max_deletions_per_tick(max)?;
+        Ok(self)
+    }
+
+    pub fn with_max_bytes_per_hour(mut self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.limits = self.limits.with_max_bytes_per_hour(max)?;
+        Ok(self)
+    }
+
+    pub fn with_max_deletion_fraction(mut self, fraction: f64) -> Result<Self, Box<EvalAltResult>> {
+        self.circuit_breaker = self.circuit_breaker.with_max_deletion_fraction(fraction)?;
+        Ok(self)
+    }
+
+    pub fn with_min_torrent_count(mut self, min: i64) -> Result<Self, Box<EvalAltResult>> {
+        self.circuit_breaker = self.circuit_breaker.with_min_torrent_count(min)?;
+        Ok(self)
+    }
+
+    pub fn with_webhook(mut self, webhook: Webhook) -> Self {
+        self.webhooks.push(webhook);
+        self
+    }
+
+    pub fn with_webhook_url(self, url: &str) -> Self {
+        self.with_webhook(Webhook::new(url))
+    }
+
+    pub fn with_ca_bundle(mut self, path: &str) -> Result<Self, Box<EvalAltResult>> {
+        self.tls = self.tls.with_ca_bundle(path)?;
+        Ok(self)
+    }
+
+    pub fn with_client_certificate(
+        mut self,
+        certificate: &str,
+        key: &str,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        self.tls = self.tls.with_client_certificate(certificate, key)?;
+        Ok(self)
+    }
+
+    pub fn with_insecure_skip_verify(mut self, insecure_skip_verify: bool) -> Self {
+        self.tls = self.tls.with_insecure_skip_verify(insecure_skip_verify);
         self
     }
 
-    pub fn with_poll_interval(mut self, interval: &str) -> Result<Self, Box<EvalAltResult>> {
-        self.poll_interval =
-            Duration::from_std(parse_duration::parse(interval).map_err(|e| format!("{e}"))?)
-                .map_err(|e| format!("{e}"))?;
+    pub fn with_rpc_timeout(mut self, timeout: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.rpc = self.rpc.with_timeout(timeout)?;
+        Ok(self)
+    }
+
+    pub fn with_rpc_retries(mut self, retries: i64) -> Result<Self, Box<EvalAltResult>> {
+        self.rpc = self.rpc.with_retries(retries)?;
+        Ok(self)
+    }
+
+    pub fn with_max_poll_backoff(mut self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        self.rpc = self.rpc.with_max_poll_backoff(max)?;
+        Ok(self)
+    }
+
+    pub fn with_action_window(
+        mut self,
+        window: &str,
+        timezone: &str,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.action_window.is_some(), "action_window");
+        self.action_window = Some(ActionWindow::from_rhai(window, timezone)?);
+        Ok(self)
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        warn_if_overridden(self.dry_run.is_some(), "dry_run");
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    pub fn with_warn_unmanaged_bytes(mut self, max: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.warn_unmanaged_bytes.is_some(), "warn_unmanaged_bytes");
+        self.warn_unmanaged_bytes = Some(ByteSize::from_rhai(max)?);
         Ok(self)
     }
 }
 
+impl Transmission {
+    /// Checks the settings that builders validate as they go, for
+    /// configs that weren't made with the builders.
+    pub fn sanity_check(&self) -> Result<(), String> {
+        if let Some(password) = &self.password {
+            password.reveal().map_err(|e| format!("{e:#}"))?;
+        }
+        if let Some(interval) = self.poll_interval {
+            if interval <= Duration::zero() {
+                return Err(format!(
+                    "poll_interval must be longer than zero, got {interval}"
+                ));
+            }
+        }
+        if let Some(fraction) = self.circuit_breaker.max_deletion_fraction {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(format!(
+                    "max_deletion_fraction must be between 0.0 and 1.0, got {fraction}"
+                ));
+            }
+        }
+        if self.rpc.timeout <= Duration::zero() {
+            return Err("rpc_timeout must be longer than zero".to_string());
+        }
+        if self.rpc.retries > MAX_RPC_RETRIES {
+            return Err(format!(
+                "rpc_retries must be between 0 and {MAX_RPC_RETRIES}, got {}",
+                self.rpc.retries
+            ));
+        }
+        for webhook in self.webhooks.iter() {
+            webhook.sanity_check()?;
+        }
+        self.tls.http_client().map_err(|e| format!("{e:#}"))?;
+        Ok(())
+    }
+}
+
 impl fmt::Debug for Transmission {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Transmission({self})")
@@ -66,6 +303,9 @@ impl fmt::Debug for Transmission {
 
 impl fmt::Display for Transmission {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{name}: ")?;
+        }
         match (&self.user, &self.password) {
             (Some(user), Some(_password)) => write!(f, "{} # u:{}:***", self.url, user),
             (Some(user), None) => write!(f, "{} # u:{}", self.url, user),
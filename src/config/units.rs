@@ -0,0 +1,156 @@
+//! Typed durations and byte sizes for the config, written like
+//! `duration("3 days")` and `bytes("500 GiB")`.
+//!
+//! Builders that take a duration or a byte size accept these values
+//! as well as the strings they're made from.
+
+use std::fmt;
+
+use chrono::Duration;
+use rhai::{CustomType, Dynamic, EvalAltResult, TypeBuilder};
+
+use crate::util::{format_duration, parse_byte_size, parse_duration};
+
+/// A span of time, like `duration("3 days")`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, CustomType)]
+#[rhai_type(name = "Duration", extra = Self::build_rhai)]
+pub struct TimeSpan(#[rhai_type(skip)] pub Duration);
+
+impl TimeSpan {
+    fn build_rhai(builder: &mut TypeBuilder<Self>) {
+        builder
+            .with_fn("duration", Self::parse)
+            .with_fn("to_string", |span: &mut Self| span.to_string())
+            .with_fn("to_debug", |span: &mut Self| {
+                format!("duration({:?})", span.to_string())
+            })
+            .with_fn("==", |a: Self, b: Self| a == b)
+            .with_fn("!=", |a: Self, b: Self| a != b)
+            .with_fn("<", |a: Self, b: Self| a < b)
+            .with_fn("<=", |a: Self, b: Self| a <= b)
+            .with_fn(">", |a: Self, b: Self| a > b)
+            .with_fn(">=", |a: Self, b: Self| a >= b)
+            .with_fn("+", Self::add);
+    }
+
+    pub fn parse(duration: &str) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self(parse_duration(duration)?))
+    }
+
+    fn add(a: Self, b: Self) -> Result<Self, Box<EvalAltResult>> {
+        a.0.checked_add(&b.0)
+            .map(Self)
+            .ok_or_else(|| format!("Duration {a} + {b} is out of range").into())
+    }
+
+    /// Takes a duration that was passed to a builder, either as a
+    /// `duration(...)` or as a string.
+    pub(crate) fn from_rhai(value: Dynamic) -> Result<Duration, Box<EvalAltResult>> {
+        if value.is_string() {
+            return Ok(Self::parse(&value.into_immutable_string()?)?.0);
+        }
+        let type_name = value.type_name();
+        value.try_cast::<Self>().map(|span| span.0).ok_or_else(|| {
+            format!("Expected a duration like \"3 days\" or duration(\"3 days\"), got {type_name}")
+                .into()
+        })
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_duration(self.0))
+    }
+}
+
+/// A number of bytes, like `bytes("500 GiB")`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, CustomType)]
+#[rhai_type(name = "ByteSize", extra = Self::build_rhai)]
+pub struct ByteSize(#[rhai_type(skip)] pub u64);
+
+impl ByteSize {
+    fn build_rhai(builder: &mut TypeBuilder<Self>) {
+        builder
+            .with_fn("bytes", Self::parse)
+            .with_fn("to_string", |size: &mut Self| size.to_string())
+            .with_fn("to_debug", |size: &mut Self| {
+                format!("bytes({:?})", size.to_string())
+            })
+            .with_fn("==", |a: Self, b: Self| a == b)
+            .with_fn("!=", |a: Self, b: Self| a != b)
+            .with_fn("<", |a: Self, b: Self| a < b)
+            .with_fn("<=", |a: Self, b: Self| a <= b)
+            .with_fn(">", |a: Self, b: Self| a > b)
+            .with_fn(">=", |a: Self, b: Self| a >= b)
+            .with_fn("+", Self::add);
+    }
+
+    pub fn parse(size: &str) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self(parse_byte_size(size)?))
+    }
+
+    fn add(a: Self, b: Self) -> Result<Self, Box<EvalAltResult>> {
+        a.0.checked_add(b.0)
+            .map(Self)
+            .ok_or_else(|| format!("Byte size {a} + {b} is out of range").into())
+    }
+
+    /// Takes a byte size that was passed to a builder, either as a
+    /// `bytes(...)` or as a string.
+    pub(crate) fn from_rhai(value: Dynamic) -> Result<u64, Box<EvalAltResult>> {
+        if value.is_string() {
+            return Ok(Self::parse(&value.into_immutable_string()?)?.0);
+        }
+        let type_name = value.type_name();
+        value.try_cast::<Self>().map(|size| size.0).ok_or_else(|| {
+            format!("Expected a byte size like \"500 GiB\" or bytes(\"500 GiB\"), got {type_name}")
+                .into()
+        })
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// Writes the size in the largest binary unit that represents it
+    /// exactly, like `500 GiB`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (shift, unit) in [
+            (50, "PiB"),
+            (40, "TiB"),
+            (30, "GiB"),
+            (20, "MiB"),
+            (10, "KiB"),
+        ] {
+            if self.0 != 0 && self.0.is_multiple_of(1 << shift) {
+                return write!(f, "{} {unit}", self.0 >> shift);
+            }
+        }
+        write!(f, "{} B", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("3 days", "3d"; "days")]
+    #[test_case("90min", "1h 30min"; "mixed units")]
+    #[test_case("1 day 2s", "1d 2s"; "gap between units")]
+    #[test_case("1.5s", "1s 500ms"; "fractional")]
+    #[test_case("0s", "0s"; "zero")]
+    fn formats_durations(input: &str, expected: &str) {
+        let span = TimeSpan::parse(input).unwrap();
+        assert_eq!(span.to_string(), expected);
+        assert_eq!(TimeSpan::parse(expected).unwrap(), span);
+    }
+
+    #[test_case("500 GiB", "500 GiB"; "binary")]
+    #[test_case("2048 KiB", "2 MiB"; "larger unit")]
+    #[test_case("1 kB", "1000 B"; "decimal")]
+    #[test_case("0", "0 B"; "zero")]
+    fn formats_byte_sizes(input: &str, expected: &str) {
+        let size = ByteSize::parse(input).unwrap();
+        assert_eq!(size.to_string(), expected);
+        assert_eq!(ByteSize::parse(expected).unwrap(), size);
+    }
+}
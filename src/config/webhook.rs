@@ -0,0 +1,149 @@
+use std::fmt;
+
+use super::lint::warn_if_overridden;
+use super::units::TimeSpan;
+use crate::util::chrono_duration;
+use chrono::Duration;
+use rhai::{CustomType, Dynamic, EvalAltResult, TypeBuilder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+pub const DEFAULT_WEBHOOK_TIMEOUT_SECS: i64 = 10;
+pub const DEFAULT_WEBHOOK_RETRIES: u32 = 3;
+/// Retries back off exponentially, so more than a few of them would
+/// keep retrying a broken endpoint for a long time.
+pub const MAX_WEBHOOK_RETRIES: u32 = 10;
+
+fn default_timeout() -> Duration {
+    Duration::seconds(DEFAULT_WEBHOOK_TIMEOUT_SECS)
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_WEBHOOK_RETRIES
+}
+
+/// An HTTP endpoint that gets a JSON summary of each tick's actions POSTed to it.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize, JsonSchema, CustomType)]
+#[rhai_type(extra = Self::build_rhai)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    #[rhai_type(readonly)]
+    pub url: String,
+
+    /// Whether to also send the actions that a dry run would have taken.
+    #[rhai_type(readonly)]
+    #[serde(default)]
+    pub include_dry_run: bool,
+
+    /// A template for the request body, for endpoints that expect
+    /// their own payload format. See [`crate::notify`] for the
+    /// placeholders that get filled in.
+    #[rhai_type(readonly)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// How long to wait for each delivery attempt.
+    #[rhai_type(readonly)]
+    #[serde(with = "chrono_duration", default = "default_timeout")]
+    #[schemars(with = "String")]
+    pub timeout: Duration,
+
+    /// How often to retry a failed delivery, at most
+    /// [`MAX_WEBHOOK_RETRIES`] times.
+    #[rhai_type(skip)]
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+impl Webhook {
+    fn build_rhai(builder: &mut TypeBuilder<Self>) {
+        builder
+            .with_fn("notify_webhook", Self::new)
+            .with_fn("include_dry_run", Self::with_include_dry_run)
+            .with_fn("template", Self::with_template)
+            .with_fn("timeout", Self::with_timeout)
+            .with_fn("retries", Self::with_retries);
+    }
+
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            include_dry_run: false,
+            template: None,
+            timeout: default_timeout(),
+            retries: default_retries(),
+        }
+    }
+
+    pub fn with_include_dry_run(self, include_dry_run: bool) -> Self {
+        warn_if_overridden(self.include_dry_run, "include_dry_run");
+        Self {
+            include_dry_run,
+            ..self
+        }
+    }
+
+    pub fn with_template(self, template: &str) -> Self {
+        warn_if_overridden(self.template.is_some(), "template");
+        Self {
+            template: Some(template.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.timeout != default_timeout(), "timeout");
+        let timeout = TimeSpan::from_rhai(timeout)?;
+        if timeout <= Duration::zero() {
+            Err("Webhook timeout must be longer than zero")?;
+        }
+        Ok(Self { timeout, ..self })
+    }
+
+    pub fn with_retries(self, retries: i64) -> Result<Self, Box<EvalAltResult>> {
+        warn_if_overridden(self.retries != default_retries(), "retries");
+        let retries = u32::try_from(retries)
+            .ok()
+            .filter(|retries| *retries <= MAX_WEBHOOK_RETRIES)
+            .ok_or_else(|| {
+                format!("retries must be between 0 and {MAX_WEBHOOK_RETRIES}, got {retries}")
+            })?;
+        Ok(Self { retries, ..self })
+    }
+
+    /// Checks the settings that builders validate as they go, for
+    /// configs that weren't made with the builders.
+    pub fn sanity_check(&self) -> Result<(), String> {
+        match Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => {
+                return Err(format!(
+                    "Webhook {}: expected an http or https URL, got scheme {:?}",
+                    self.url,
+                    url.scheme()
+                ))
+            }
+            Err(e) => return Err(format!("Webhook {:?}: invalid URL: {e}", self.url)),
+        }
+        if self.timeout <= Duration::zero() {
+            return Err(format!(
+                "Webhook {}: timeout must be longer than zero",
+                self.url
+            ));
+        }
+        if self.retries > MAX_WEBHOOK_RETRIES {
+            return Err(format!(
+                "Webhook {}: retries must be between 0 and {MAX_WEBHOOK_RETRIES}, got {}",
+                self.url, self.retries
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Webhook({})", self.url)
+    }
+}
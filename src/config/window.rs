@@ -0,0 +1,150 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rhai::EvalAltResult;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// A daily window of local time in which torrents may be deleted,
+/// e.g. `02:00-06:00` in `Europe/Berlin`. A window whose end is
+/// before its start extends past midnight.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawActionWindow", into = "RawActionWindow")]
+pub struct ActionWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+}
+
+/// How an [`ActionWindow`] is written down, both in the config and
+/// when serialized.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "ActionWindow")]
+#[serde(deny_unknown_fields)]
+struct RawActionWindow {
+    /// The start and end of the window, like `"02:00-06:00"`.
+    window: String,
+    /// An IANA time zone name, like `"Europe/Berlin"`.
+    timezone: String,
+}
+
+impl JsonSchema for ActionWindow {
+    fn schema_name() -> String {
+        RawActionWindow::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        RawActionWindow::json_schema(generator)
+    }
+}
+
+impl TryFrom<RawActionWindow> for ActionWindow {
+    type Error = String;
+
+    fn try_from(raw: RawActionWindow) -> Result<Self, Self::Error> {
+        ActionWindow::parse(&raw.window, &raw.timezone)
+    }
+}
+
+impl From<ActionWindow> for RawActionWindow {
+    fn from(window: ActionWindow) -> Self {
+        RawActionWindow {
+            window: format!(
+                "{}-{}",
+                window.start.format("%H:%M"),
+                window.end.format("%H:%M")
+            ),
+            timezone: window.timezone.name().to_string(),
+        }
+    }
+}
+
+impl ActionWindow {
+    /// Parses a window like `"02:00-06:00"` in the IANA time zone
+    /// `timezone`.
+    pub fn parse(window: &str, timezone: &str) -> Result<Self, String> {
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("Action window {window:?} must look like \"02:00-06:00\""))?;
+        let time = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M")
+                .map_err(|e| format!("Invalid time {s:?} in action window {window:?}: {e}"))
+        };
+        let (start, end) = (time(start)?, time(end)?);
+        if start == end {
+            return Err(format!("Action window {window:?} is empty"));
+        }
+        let timezone = timezone
+            .parse()
+            .map_err(|e| format!("Invalid time zone {timezone:?}: {e}"))?;
+        Ok(ActionWindow {
+            start,
+            end,
+            timezone,
+        })
+    }
+
+    pub(crate) fn from_rhai(window: &str, timezone: &str) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self::parse(window, timezone)?)
+    }
+
+    /// Returns true if `now` falls into the window.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.timezone).time();
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl fmt::Display for ActionWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} {}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            self.timezone
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("02:00-06:00", "2025-01-15T01:30:00Z", true; "berlin night in winter")]
+    #[test_case("02:00-06:00", "2025-01-15T05:00:00Z", false; "berlin morning in winter")]
+    #[test_case("02:00-06:00", "2025-07-15T03:30:00Z", true; "berlin night in summer")]
+    #[test_case("02:00-06:00", "2025-07-15T04:00:00Z", false; "window end is exclusive")]
+    #[test_case("22:00-04:00", "2025-01-15T22:30:00Z", true; "wrapping, before midnight")]
+    #[test_case("22:00-04:00", "2025-01-15T02:30:00Z", true; "wrapping, after midnight")]
+    #[test_case("22:00-04:00", "2025-01-15T12:00:00Z", false; "wrapping, midday")]
+    fn contains(window: &str, now: &str, expected: bool) {
+        let window = ActionWindow::parse(window, "Europe/Berlin").unwrap();
+        assert_eq!(window.contains(now.parse().unwrap()), expected);
+    }
+
+    #[test_case("02:00", "UTC"; "no end")]
+    #[test_case("02:00-25:00", "UTC"; "bad time")]
+    #[test_case("02:00-02:00", "UTC"; "empty")]
+    #[test_case("02:00-06:00", "Mars/Olympus_Mons"; "bad time zone")]
+    fn rejects(window: &str, timezone: &str) {
+        assert!(ActionWindow::parse(window, timezone).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let window = ActionWindow::parse("22:00-04:30", "America/New_York").unwrap();
+        let json = serde_json::to_string(&window).unwrap();
+        assert_eq!(
+            json,
+            r#"{"window":"22:00-04:30","timezone":"America/New_York"}"#
+        );
+        assert_eq!(serde_json::from_str::<ActionWindow>(&json).unwrap(), window);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod audit;
 pub mod config;
+pub mod limits;
+pub mod notify;
+pub mod state;
 mod util;
 
 use anyhow::anyhow;
@@ -19,6 +23,7 @@ pub struct Torrent {
     pub error_string: String,
     pub upload_ratio: f32,
     pub computed_upload_ratio: f64,
+    pub uploaded_ever: i64,
     pub status: TorrentStatus,
     pub num_files: usize,
     pub total_size: usize,
@@ -36,6 +41,7 @@ impl std::fmt::Debug for Torrent {
             .field("error_string", &self.error_string)
             .field("upload_ratio", &self.upload_ratio)
             .field("computed_upload_ratio", &self.computed_upload_ratio)
+            .field("uploaded_ever", &self.uploaded_ever)
             .field("status", &self.status)
             .field("num_files", &self.num_files)
             .field("total_size", &self.total_size)
@@ -92,6 +98,7 @@ impl TryFrom<transmission_rpc::types::Torrent> for Torrent {
             error_string: ensure_field(t.error_string, "error_string")?,
             upload_ratio: ensure_field(t.upload_ratio, "upload_ratio")?,
             computed_upload_ratio,
+            uploaded_ever,
             status: ensure_field(t.status, "status")?,
             num_files: ensure_field(t.files, "files")?.len(),
             total_size: ensure_field(t.total_size, "total_size")? as usize,
@@ -0,0 +1,612 @@
+//! Enforcement of [`DeletionLimits`] across ticks, and the
+//! [`CircuitBreaker`] safety net.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::warn;
+
+use crate::config::{
+    limits::{CircuitBreaker, DeletionLimits},
+    policy::ConditionMatch,
+    policy::DeletePolicy,
+};
+use crate::Torrent;
+
+/// A torrent that a policy has decided to delete.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletionCandidate<'a> {
+    pub torrent: &'a Torrent,
+    pub policy_index: usize,
+    pub policy: &'a DeletePolicy,
+    pub reason: ConditionMatch,
+}
+
+impl DeletionCandidate<'_> {
+    /// The name under which the governing policy is tracked.
+    pub fn policy_name(&self) -> String {
+        self.policy.name_or_index(self.policy_index).into_owned()
+    }
+}
+
+/// The result of applying deletion limits to a set of candidates.
+#[derive(Debug, Default)]
+pub struct Admission<'a> {
+    /// Candidates that may be deleted in this tick.
+    pub admitted: Vec<DeletionCandidate<'a>>,
+
+    /// Candidates that exceeded a limit and have to wait for a later tick.
+    pub deferred: Vec<DeletionCandidate<'a>>,
+}
+
+#[derive(Debug, Default)]
+struct History(VecDeque<(DateTime<Utc>, u64)>);
+
+impl History {
+    fn expire(&mut self, now: DateTime<Utc>) {
+        while let Some((at, _)) = self.0.front() {
+            if now - *at < Duration::hours(1) {
+                break;
+            }
+            self.0.pop_front();
+        }
+    }
+
+    fn bytes_in_window(&self) -> u64 {
+        self.0.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Book-keeping for one instance or policy during a single admission run.
+#[derive(Debug, Default)]
+struct Budget {
+    count: usize,
+    bytes: u64,
+}
+
+impl Budget {
+    fn fits(&self, limits: &DeletionLimits, history: Option<&History>, size: u64) -> bool {
+        let count_ok = limits
+            .max_deletions_per_tick
+            .map(|max| self.count < max)
+            .unwrap_or(true);
+        let bytes_ok = limits
+            .max_bytes_per_hour
+            .map(|max| {
+                let used = history.map(History::bytes_in_window).unwrap_or(0) + self.bytes;
+                // A torrent larger than the hourly limit could never
+                // fit, so it goes on its own once nothing else got
+                // deleted within the hour:
+                used + size <= max || (size > max && used == 0)
+            })
+            .unwrap_or(true);
+        count_ok && bytes_ok
+    }
+
+    fn take(&mut self, size: u64) {
+        self.count += 1;
+        self.bytes += size;
+    }
+}
+
+/// Keeps track of what got deleted on one transmission instance, so
+/// that [`DeletionLimits`] can be enforced across ticks.
+#[derive(Debug, Default)]
+pub struct DeletionLimiter {
+    instance: History,
+    policies: HashMap<String, History>,
+}
+
+impl DeletionLimiter {
+    /// Splits `candidates` into those that may be deleted now and
+    /// those that have to be deferred.
+    ///
+    /// Candidates are considered oldest-first (by the time they
+    /// finished downloading, then by hash), so the outcome only
+    /// depends on the candidates and past deletions. A candidate
+    /// that doesn't fit is deferred without holding up the ones after
+    /// it. One that is larger than a `max_bytes_per_hour` limit gets
+    /// admitted on its own, once nothing else was deleted under that
+    /// limit within the hour. Torrents that
+    /// were matched by more than one policy are only considered once,
+    /// under the first policy that matched them.
+    pub fn admit<'a>(
+        &mut self,
+        now: DateTime<Utc>,
+        instance_limits: &DeletionLimits,
+        mut candidates: Vec<DeletionCandidate<'a>>,
+    ) -> Admission<'a> {
+        self.instance.expire(now);
+        for history in self.policies.values_mut() {
+            history.expire(now);
+        }
+
+        let mut seen = HashSet::new();
+        candidates.retain(|c| seen.insert(c.torrent.hash.clone()));
+        candidates.sort_by(|a, b| {
+            // Torrents without a done date sort last:
+            (
+                a.torrent.done_date.is_none(),
+                a.torrent.done_date,
+                &a.torrent.hash,
+            )
+                .cmp(&(
+                    b.torrent.done_date.is_none(),
+                    b.torrent.done_date,
+                    &b.torrent.hash,
+                ))
+        });
+
+        let mut instance_budget = Budget::default();
+        let mut policy_budgets: HashMap<String, Budget> = Default::default();
+        let mut admission = Admission::default();
+        for candidate in candidates {
+            let size = candidate.torrent.total_size as u64;
+            let policy_name = candidate.policy_name();
+            let policy_budget = policy_budgets.entry(policy_name.clone()).or_default();
+            if policy_budget.fits(
+                &candidate.policy.limits,
+                self.policies.get(&policy_name),
+                size,
+            ) && instance_budget.fits(instance_limits, Some(&self.instance), size)
+            {
+                let max_bytes = [
+                    candidate.policy.limits.max_bytes_per_hour,
+                    instance_limits.max_bytes_per_hour,
+                ];
+                if max_bytes.into_iter().flatten().any(|max| size > max) {
+                    warn!(
+                        torrent = ?candidate.torrent.name,
+                        size,
+                        matched_policy = ?policy_name,
+                        "Torrent is larger than max_bytes_per_hour, deleting it on its own",
+                    );
+                }
+                policy_budget.take(size);
+                instance_budget.take(size);
+                admission.admitted.push(candidate);
+            } else {
+                admission.deferred.push(candidate);
+            }
+        }
+        admission
+    }
+
+    /// Records that `deleted` were actually deleted at `now`.
+    pub fn record(&mut self, now: DateTime<Utc>, deleted: &[DeletionCandidate<'_>]) {
+        for candidate in deleted {
+            let size = candidate.torrent.total_size as u64;
+            self.instance.0.push_back((now, size));
+            self.policies
+                .entry(candidate.policy_name())
+                .or_default()
+                .0
+                .push_back((now, size));
+        }
+    }
+}
+
+/// The reason the circuit breaker refused to let a tick delete anything.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trip {
+    /// The instance reported fewer torrents than expected.
+    TooFewTorrents { count: usize, min: usize },
+
+    /// The tick would delete too large a fraction of all torrents.
+    TooManyTorrents { fraction: f64, max: f64 },
+
+    /// The tick would delete too large a fraction of all data.
+    TooManyBytes { fraction: f64, max: f64 },
+}
+
+impl fmt::Display for Trip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trip::TooFewTorrents { count, min } => {
+                write!(
+                    f,
+                    "instance reported {count} torrents, expected at least {min}"
+                )
+            }
+            Trip::TooManyTorrents { fraction, max } => write!(
+                f,
+                "would delete {:.1}% of torrents, limit is {:.1}%",
+                fraction * 100.0,
+                max * 100.0
+            ),
+            Trip::TooManyBytes { fraction, max } => write!(
+                f,
+                "would delete {:.1}% of data, limit is {:.1}%",
+                fraction * 100.0,
+                max * 100.0
+            ),
+        }
+    }
+}
+
+/// Refuses deletions on an instance when a tick looks like it would
+/// remove far more than it should.
+///
+/// Once a tick that would actually delete something trips it, the
+/// breaker stays tripped until the process is restarted (e.g. after
+/// a config change). Trips in ticks that wouldn't delete anything,
+/// like dry runs or ticks that saw an empty torrent list, only last
+/// for that tick.
+#[derive(Debug, Default)]
+pub struct SafetyBreaker {
+    tripped: Option<Trip>,
+}
+
+impl SafetyBreaker {
+    /// Returns the reason that the breaker tripped, if it did.
+    pub fn tripped(&self) -> Option<Trip> {
+        self.tripped
+    }
+
+    /// Checks whether deleting everything in `matched` out of
+    /// `torrents` is acceptable under `config`.
+    ///
+    /// `matched` should hold every candidate that policies matched,
+    /// before action windows and deletion limits hold any of them
+    /// back, so that limits can't hide a policy that matches far too
+    /// much. If the check fails and `deleting` is true (the tick
+    /// would delete something), the breaker latches.
+    pub fn check(
+        &mut self,
+        config: &CircuitBreaker,
+        torrents: &[Torrent],
+        matched: &[DeletionCandidate<'_>],
+        deleting: bool,
+    ) -> Result<(), Trip> {
+        if let Some(trip) = self.tripped {
+            return Err(trip);
+        }
+        let trip = Self::evaluate(config, torrents, matched);
+        if deleting {
+            self.tripped = trip;
+        }
+        trip.map_or(Ok(()), Err)
+    }
+
+    fn evaluate(
+        config: &CircuitBreaker,
+        torrents: &[Torrent],
+        matched: &[DeletionCandidate<'_>],
+    ) -> Option<Trip> {
+        let min = config
+            .min_torrent_count
+            .unwrap_or(config.max_deletion_fraction.map_or(0, |_| 1));
+        if torrents.len() < min {
+            return Some(Trip::TooFewTorrents {
+                count: torrents.len(),
+                min,
+            });
+        }
+
+        let max = config.max_deletion_fraction?;
+        // Torrents that more than one policy matched only get deleted once:
+        let mut seen = HashSet::new();
+        let deleting: Vec<_> = matched
+            .iter()
+            .filter(|c| seen.insert(&c.torrent.hash))
+            .collect();
+        if !torrents.is_empty() {
+            let fraction = deleting.len() as f64 / torrents.len() as f64;
+            if fraction > max {
+                return Some(Trip::TooManyTorrents { fraction, max });
+            }
+        }
+        let total_bytes: usize = torrents.iter().map(|t| t.total_size).sum();
+        if total_bytes > 0 {
+            let deleting_bytes: usize = deleting.iter().map(|c| c.torrent.total_size).sum();
+            let fraction = deleting_bytes as f64 / total_bytes as f64;
+            if fraction > max {
+                return Some(Trip::TooManyBytes { fraction, max });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::policy::{Condition, PolicyMatch};
+    use transmission_rpc::types::{ErrorType, TorrentStatus};
+
+    fn torrent(hash: &str, age_days: i64, total_size: usize) -> Torrent {
+        Torrent {
+            id: 1,
+            hash: hash.to_string(),
+            name: hash.to_string(),
+            done_date: Some(Utc::now() - Duration::days(age_days)),
+            error: ErrorType::Ok,
+            error_string: "".to_string(),
+            upload_ratio: 2.0,
+            computed_upload_ratio: 2.0,
+            uploaded_ever: 0,
+            status: TorrentStatus::Seeding,
+            num_files: 1,
+            total_size,
+            trackers: vec![],
+        }
+    }
+
+    fn policy(limits: DeletionLimits) -> DeletePolicy {
+        DeletePolicy {
+            limits,
+            ..DeletePolicy::new_real(
+                "test",
+                PolicyMatch::default(),
+                Condition::new().unwrap().with_max_ratio(1.0),
+            )
+            .unwrap()
+        }
+    }
+
+    fn candidates<'a>(
+        policy: &'a DeletePolicy,
+        torrents: &'a [Torrent],
+    ) -> Vec<DeletionCandidate<'a>> {
+        torrents
+            .iter()
+            .map(|torrent| DeletionCandidate {
+                torrent,
+                policy_index: 0,
+                policy,
+                reason: ConditionMatch::Ratio(2.0),
+            })
+            .collect()
+    }
+
+    fn hashes(candidates: &[DeletionCandidate<'_>]) -> Vec<String> {
+        candidates.iter().map(|c| c.torrent.hash.clone()).collect()
+    }
+
+    #[test]
+    fn per_tick_limit_defers_youngest() {
+        let policy = policy(DeletionLimits {
+            max_deletions_per_tick: Some(2),
+            ..Default::default()
+        });
+        let torrents = [
+            torrent("b", 3, 10),
+            torrent("a", 1, 10),
+            torrent("c", 5, 10),
+        ];
+        let mut limiter = DeletionLimiter::default();
+        let admission = limiter.admit(
+            Utc::now(),
+            &Default::default(),
+            candidates(&policy, &torrents),
+        );
+        assert_eq!(hashes(&admission.admitted), vec!["c", "b"]);
+        assert_eq!(hashes(&admission.deferred), vec!["a"]);
+    }
+
+    #[test]
+    fn instance_limit_applies_across_policies() {
+        let policy = policy(Default::default());
+        let torrents = [torrent("a", 3, 10), torrent("b", 2, 10)];
+        let mut limiter = DeletionLimiter::default();
+        let limits = DeletionLimits {
+            max_deletions_per_tick: Some(1),
+            ..Default::default()
+        };
+        let admission = limiter.admit(Utc::now(), &limits, candidates(&policy, &torrents));
+        assert_eq!(hashes(&admission.admitted), vec!["a"]);
+        assert_eq!(hashes(&admission.deferred), vec!["b"]);
+    }
+
+    #[test]
+    fn hourly_byte_limit_spans_ticks() {
+        let policy = policy(DeletionLimits {
+            max_bytes_per_hour: Some(25),
+            ..Default::default()
+        });
+        let torrents = [
+            torrent("a", 3, 10),
+            torrent("b", 2, 10),
+            torrent("c", 1, 10),
+        ];
+        let mut limiter = DeletionLimiter::default();
+        let now = Utc::now();
+
+        let admission = limiter.admit(now, &Default::default(), candidates(&policy, &torrents));
+        assert_eq!(hashes(&admission.admitted), vec!["a", "b"]);
+        limiter.record(now, &admission.admitted);
+
+        let admission = limiter.admit(
+            now + Duration::minutes(30),
+            &Default::default(),
+            candidates(&policy, &torrents[2..]),
+        );
+        assert!(admission.admitted.is_empty());
+
+        let admission = limiter.admit(
+            now + Duration::minutes(61),
+            &Default::default(),
+            candidates(&policy, &torrents[2..]),
+        );
+        assert_eq!(hashes(&admission.admitted), vec!["c"]);
+    }
+
+    #[test]
+    fn oversized_torrent_goes_alone_without_blocking_others() {
+        let policy = policy(DeletionLimits {
+            max_bytes_per_hour: Some(25),
+            ..Default::default()
+        });
+        let torrents = [
+            torrent("huge", 3, 100),
+            torrent("a", 2, 10),
+            torrent("b", 1, 10),
+        ];
+        let mut limiter = DeletionLimiter::default();
+        let now = Utc::now();
+
+        // Nothing was deleted within the hour, so the oversized
+        // torrent goes first, alone:
+        let admission = limiter.admit(now, &Default::default(), candidates(&policy, &torrents));
+        assert_eq!(hashes(&admission.admitted), vec!["huge"]);
+        assert_eq!(hashes(&admission.deferred), vec!["a", "b"]);
+        limiter.record(now, &admission.admitted);
+
+        // After that, it doesn't hold up the small ones:
+        let now = now + Duration::minutes(61);
+        let admission = limiter.admit(
+            now,
+            &Default::default(),
+            candidates(&policy, &torrents[1..]),
+        );
+        assert_eq!(hashes(&admission.admitted), vec!["a", "b"]);
+        limiter.record(now, &admission.admitted);
+
+        // And another oversized torrent has to wait for the hour
+        // to clear:
+        let admission = limiter.admit(
+            now + Duration::minutes(10),
+            &Default::default(),
+            candidates(&policy, &torrents[..1]),
+        );
+        assert!(admission.admitted.is_empty());
+        assert_eq!(hashes(&admission.deferred), vec!["huge"]);
+    }
+
+    #[test]
+    fn deferred_candidate_does_not_block_smaller_ones() {
+        let policy = policy(DeletionLimits {
+            max_bytes_per_hour: Some(25),
+            ..Default::default()
+        });
+        let torrents = [
+            torrent("a", 3, 10),
+            torrent("big", 2, 20),
+            torrent("c", 1, 10),
+        ];
+        let admission = DeletionLimiter::default().admit(
+            Utc::now(),
+            &Default::default(),
+            candidates(&policy, &torrents),
+        );
+        assert_eq!(hashes(&admission.admitted), vec!["a", "c"]);
+        assert_eq!(hashes(&admission.deferred), vec!["big"]);
+    }
+
+    #[test]
+    fn duplicate_matches_count_once() {
+        let policy = policy(DeletionLimits {
+            max_deletions_per_tick: Some(2),
+            ..Default::default()
+        });
+        let torrents = [torrent("a", 3, 10), torrent("b", 2, 10)];
+        let mut doubled = candidates(&policy, &torrents);
+        doubled.extend(candidates(&policy, &torrents));
+        let admission = DeletionLimiter::default().admit(Utc::now(), &Default::default(), doubled);
+        assert_eq!(hashes(&admission.admitted), vec!["a", "b"]);
+        assert!(admission.deferred.is_empty());
+    }
+
+    #[test]
+    fn breaker_trips_on_large_fractions() {
+        let policy = policy(Default::default());
+        let torrents = [
+            torrent("a", 3, 10),
+            torrent("b", 2, 10),
+            torrent("c", 1, 1000),
+        ];
+        let config = CircuitBreaker {
+            max_deletion_fraction: Some(0.5),
+            ..Default::default()
+        };
+
+        let mut breaker = SafetyBreaker::default();
+        let by_count = candidates(&policy, &torrents[..2]);
+        assert!(matches!(
+            breaker.check(&config, &torrents, &by_count, true),
+            Err(Trip::TooManyTorrents { .. })
+        ));
+
+        let mut breaker = SafetyBreaker::default();
+        let by_size = candidates(&policy, &torrents[2..]);
+        assert!(matches!(
+            breaker.check(&config, &torrents, &by_size, true),
+            Err(Trip::TooManyBytes { .. })
+        ));
+
+        let mut breaker = SafetyBreaker::default();
+        let acceptable = candidates(&policy, &torrents[..1]);
+        assert_eq!(breaker.check(&config, &torrents, &acceptable, true), Ok(()));
+    }
+
+    #[test]
+    fn breaker_latches_only_when_deleting() {
+        let policy = policy(Default::default());
+        let torrents = [torrent("a", 3, 10), torrent("b", 2, 10)];
+        let config = CircuitBreaker {
+            max_deletion_fraction: Some(0.5),
+            ..Default::default()
+        };
+        let mut breaker = SafetyBreaker::default();
+        // A transient empty torrent list trips the breaker for that
+        // tick only, since nothing would get deleted:
+        assert_eq!(
+            breaker.check(&config, &[], &[], false),
+            Err(Trip::TooFewTorrents { count: 0, min: 1 })
+        );
+        let acceptable = candidates(&policy, &torrents[..1]);
+        assert_eq!(breaker.check(&config, &torrents, &acceptable, true), Ok(()));
+
+        // So does a dry run that would delete too much:
+        let everything = candidates(&policy, &torrents);
+        assert!(breaker
+            .check(&config, &torrents, &everything, false)
+            .is_err());
+        assert_eq!(breaker.check(&config, &torrents, &acceptable, true), Ok(()));
+
+        // A tick that would delete too much latches it, and even a
+        // harmless tick is refused after that:
+        assert!(breaker
+            .check(&config, &torrents, &everything, true)
+            .is_err());
+        assert!(matches!(
+            breaker.check(&config, &torrents, &acceptable, true),
+            Err(Trip::TooManyTorrents { .. })
+        ));
+        assert!(breaker.tripped().is_some());
+    }
+
+    #[test]
+    fn breaker_counts_torrents_matched_twice_once() {
+        let policy = policy(Default::default());
+        let torrents = [torrent("a", 3, 10), torrent("b", 2, 10)];
+        let config = CircuitBreaker {
+            max_deletion_fraction: Some(0.5),
+            ..Default::default()
+        };
+        let mut doubled = candidates(&policy, &torrents[..1]);
+        doubled.extend(candidates(&policy, &torrents[..1]));
+        assert_eq!(
+            SafetyBreaker::default().check(&config, &torrents, &doubled, true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn disabled_breaker_never_trips() {
+        let policy = policy(Default::default());
+        let torrents = [torrent("a", 3, 10)];
+        let mut breaker = SafetyBreaker::default();
+        assert_eq!(
+            breaker.check(
+                &Default::default(),
+                &torrents,
+                &candidates(&policy, &torrents),
+                true
+            ),
+            Ok(())
+        );
+        assert_eq!(breaker.check(&Default::default(), &[], &[], true), Ok(()));
+    }
+}
@@ -1,18 +1,42 @@
+mod api;
 mod metrics;
+mod supervisor;
 
+use api::{InstanceEntry, TickRequest, TorrentView};
 use metrics::*;
 
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use gearbox_maintenance::{
-    config::{configure, Instance},
+    audit::{AuditLog, AuditRecord},
+    config::{
+        configure_as,
+        format::{json_schema, ConfigFormat, DeclarativeConfig},
+        lint::{lint, Severity},
+        policy::ConditionMatch,
+        rpc::RpcSettings,
+        units::{ByteSize, TimeSpan},
+        Instance,
+    },
+    limits::{DeletionCandidate, DeletionLimiter, SafetyBreaker},
+    notify::{deliver, Deliveries, ReportedAction, TickReport},
+    state::StateStore,
     Torrent,
 };
 use prometheus_client::registry::Registry;
-use std::{collections::HashMap, convert::TryFrom, io, net::SocketAddr, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
 use tokio::time;
-use tracing::{debug, info, metadata::LevelFilter, warn};
+use tracing::{debug, error, info, metadata::LevelFilter, warn};
 use tracing_subscriber::EnvFilter;
 use transmission_rpc::{
     types::{BasicAuth, Id},
@@ -20,19 +44,209 @@ use transmission_rpc::{
 };
 use url::Url;
 
-#[derive(Parser, Debug)]
-#[clap(author = "Andreas Fuchs <asf@boinkor.net>")]
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    author = "Andreas Fuchs <asf@boinkor.net>",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opt {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// The config file to load
-    config: PathBuf,
+    #[clap(required = true)]
+    config: Option<PathBuf>,
+
+    #[clap(long)]
+    /// The config file's format (rhai, toml, yaml or json); guessed from its extension if unset
+    config_format: Option<ConfigFormat>,
 
     #[clap(short = 'f')]
     /// Actually perform policy actions
     take_action: bool,
 
     #[clap(long)]
-    /// Serve prometheus metrics on this network address
+    /// Never delete anything, regardless of -f and the dry_run settings in the config
+    dry_run: bool,
+
+    #[clap(long)]
+    /// Serve prometheus metrics and the JSON API on this network address
     prometheus_listen_addr: Option<SocketAddr>,
+
+    #[clap(long)]
+    /// Let ticks requested through the API delete torrents; without this, they are dry runs
+    api_allow_deletions: bool,
+
+    #[clap(long)]
+    /// Delete torrents even if the safety circuit breaker trips
+    override_circuit_breaker: bool,
+
+    #[clap(long)]
+    /// Keep a history of torrents and actions in this directory
+    state_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Append a JSON record of every executed action to this file
+    audit_log: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Histogram buckets for torrent sizes in bytes, e.g. "exponential:5e9,2,11"
+    size_buckets: Option<BucketLayout>,
+
+    #[clap(long)]
+    /// Histogram buckets for seeding age in seconds, e.g. "exponential:3600,2,12"
+    seeding_age_buckets: Option<BucketLayout>,
+
+    #[clap(long)]
+    /// Histogram buckets for upload ratios, e.g. "linear:0.5,0.5,8" or "0.5,1,2,5"
+    ratio_buckets: Option<BucketLayout>,
+
+    #[clap(long, default_value = "30s", value_parser = parse_duration::parse)]
+    /// When shutting down, how long to wait for running ticks to finish
+    shutdown_deadline: time::Duration,
+
+    #[clap(long, default_value = "3")]
+    /// Report as ready only if every instance had a successful tick within this many poll intervals
+    ready_poll_intervals: u32,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Evaluate a config file and print the resulting instances and
+    /// policies, marking settings inherited from defaults
+    Check {
+        /// The config file to check
+        config: PathBuf,
+
+        #[clap(long)]
+        /// The config file's format (rhai, toml, yaml or json); guessed from its extension if unset
+        config_format: Option<ConfigFormat>,
+    },
+
+    /// Turn a config file into an equivalent TOML, YAML or JSON file,
+    /// with defaults and top-level webhooks filled into each instance
+    Export {
+        /// The config file to export
+        config: PathBuf,
+
+        #[clap(long)]
+        /// The config file's format (rhai, toml, yaml or json); guessed from its extension if unset
+        config_format: Option<ConfigFormat>,
+
+        #[clap(long, default_value = "toml")]
+        /// The format to export to (toml, yaml or json)
+        to: ConfigFormat,
+    },
+
+    /// Look for contradictory, unreachable and overlapping policies
+    /// in a config file; fails if any policy can't work as written
+    Lint {
+        /// The config file to lint
+        config: PathBuf,
+
+        #[clap(long)]
+        /// The config file's format (rhai, toml, yaml or json); guessed from its extension if unset
+        config_format: Option<ConfigFormat>,
+    },
+
+    /// Print the JSON Schema for TOML, YAML and JSON config files
+    Schema,
+}
+
+/// Loads `config`, in the given format or the one its extension suggests.
+fn load_config(config: &Path, format: Option<ConfigFormat>) -> Result<Vec<Instance>> {
+    let format = format.unwrap_or_else(|| ConfigFormat::from_path(config));
+    configure_as(config, format).map_err(|e| anyhow!("{e}"))
+}
+
+/// Prints what `configure` made of a config file.
+fn check(instances: &[Instance]) {
+    for instance in instances {
+        let inherited = |key: &str| {
+            if instance.inherited.contains(key) {
+                " (from defaults)"
+            } else {
+                ""
+            }
+        };
+        let transmission = &instance.transmission;
+        println!("{transmission}");
+        println!(
+            "  poll interval: {}{}",
+            TimeSpan(transmission.poll_interval()),
+            inherited("transmission.poll_interval")
+        );
+        if transmission.user.is_some() {
+            println!("  credentials{}", inherited("transmission.user"));
+        }
+        if let Some(max) = transmission.limits.max_deletions_per_tick {
+            println!(
+                "  max deletions per tick: {max}{}",
+                inherited("transmission.limits.max_deletions_per_tick")
+            );
+        }
+        if let Some(max) = transmission.limits.max_bytes_per_hour {
+            println!(
+                "  max bytes per hour: {}{}",
+                ByteSize(max),
+                inherited("transmission.limits.max_bytes_per_hour")
+            );
+        }
+        if let Some(window) = &transmission.action_window {
+            println!(
+                "  action window: {window}{}",
+                inherited("transmission.action_window")
+            );
+        }
+        if let Some(dry_run) = transmission.dry_run {
+            println!("  dry run: {dry_run}{}", inherited("transmission.dry_run"));
+        }
+        for (index, policy) in instance.policies.iter().enumerate() {
+            println!("  {policy}");
+            let prefix = format!("policies.{}.", policy.name_or_index(index));
+            let from_defaults: Vec<_> = instance
+                .inherited
+                .iter()
+                .filter_map(|key| key.strip_prefix(&prefix))
+                .collect();
+            if !from_defaults.is_empty() {
+                println!("    from defaults: {}", from_defaults.join(", "));
+            }
+        }
+    }
+}
+
+/// State carried from one tick to the next on a single instance.
+#[derive(Debug, Default)]
+struct TickState {
+    limiter: DeletionLimiter,
+    breaker: SafetyBreaker,
+    store: StateStore,
+    audit: Option<Arc<AuditLog>>,
+    webhook_client: reqwest::Client,
+    deliveries: Deliveries,
+    /// The (hash, policy) pairs that were already counted as dry-run
+    /// actions and still match.
+    dry_run_actions: HashSet<(String, String)>,
+}
+
+/// Counts a dry-run action in `torrent_action_total` once for as long
+/// as the torrent keeps matching the policy, rather than on every tick.
+fn track_dry_run_action(
+    state: &mut TickState,
+    metrics: &Metrics,
+    instance: &Instance,
+    candidate: &DeletionCandidate<'_>,
+) {
+    let key = (candidate.torrent.hash.clone(), candidate.policy_name());
+    if state.dry_run_actions.insert(key) {
+        metrics.track_action(
+            &Policy::new_for(instance.name(), &candidate.policy_name()),
+            candidate.policy.action(),
+            true,
+        );
+    }
 }
 
 fn init_logging() {
@@ -50,41 +264,147 @@ fn init_logging() {
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 }
 
-#[tracing::instrument(skip(instance), fields(instance=instance.transmission.url))]
-async fn tick_on_instance(instance: &Instance, take_action: bool, metrics: &Metrics) -> Result<()> {
-    let _tick_timer = metrics.tick_duration(&instance.transmission.url);
-    let status = metrics.tick_failure_tracker(&instance.transmission.url);
+fn ids_of(candidates: &[DeletionCandidate]) -> Vec<Id> {
+    candidates
+        .iter()
+        .map(|c| Id::Hash(c.torrent.hash.to_string()))
+        .collect()
+}
+
+/// Sends `report` to every webhook on `instance` that wants it,
+/// without waiting for delivery; shutting down waits for it, though.
+fn notify_webhooks(instance: &Instance, state: &TickState, metrics: &Metrics, report: TickReport) {
+    let report = Arc::new(report);
+    for webhook in instance.transmission.webhooks.iter() {
+        if !report.is_wanted_by(webhook) {
+            continue;
+        }
+        let (client, webhook, report, metrics) = (
+            state.webhook_client.clone(),
+            webhook.clone(),
+            report.clone(),
+            metrics.clone(),
+        );
+        state.deliveries.spawn(async move {
+            let result = deliver(&client, &webhook, &report).await;
+            metrics.track_webhook_delivery(&report.instance, result.is_ok());
+            if let Err(e) = result {
+                warn!(instance=report.instance, webhook=webhook.url, error=%e, "Could not deliver webhook");
+            }
+        });
+    }
+}
+
+/// How long to wait before retrying to fetch torrents; each further
+/// retry waits twice as long.
+const INITIAL_RPC_RETRY_BACKOFF: time::Duration = time::Duration::from_secs(1);
+const MAX_RPC_RETRY_BACKOFF: time::Duration = time::Duration::from_secs(30);
+
+/// Fetches all torrents from `client`, retrying with exponential
+/// backoff if the request fails or times out.
+async fn fetch_torrents(client: &mut TransClient, rpc: &RpcSettings) -> Result<Vec<Torrent>> {
+    let timeout = rpc
+        .timeout
+        .to_std()
+        .context("RPC timeout must not be negative")?;
+    let mut backoff = INITIAL_RPC_RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result =
+            match time::timeout(timeout, client.torrent_get(Torrent::request_fields(), None)).await
+            {
+                Ok(response) => response.map_err(|e| anyhow!("{e}")),
+                Err(_) => Err(anyhow!("Timed out after {timeout:?}")),
+            };
+        match result {
+            Ok(response) => {
+                return response
+                    .arguments
+                    .torrents
+                    .into_iter()
+                    .map(Torrent::try_from)
+                    .collect()
+            }
+            Err(e) if attempt >= rpc.retries => {
+                return Err(e.context(format!(
+                    "Could not retrieve list of torrents after {} attempts",
+                    attempt + 1
+                )))
+            }
+            Err(e) => {
+                attempt += 1;
+                warn!(error=%e, attempt, "Could not retrieve list of torrents, retrying");
+                time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(MAX_RPC_RETRY_BACKOFF);
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip(entry, opt, state, metrics), fields(instance=entry.instance.name()))]
+async fn tick_on_instance(
+    entry: &InstanceEntry,
+    opt: &Opt,
+    take_action: bool,
+    state: &mut TickState,
+    metrics: &Metrics,
+) -> Result<()> {
+    let instance = &entry.instance;
+    let _tick_timer = metrics.tick_duration(instance.name());
+    let status = metrics.tick_failure_tracker(instance.name());
     let url = Url::parse(&instance.transmission.url)?;
     let basic_auth = BasicAuth {
         user: instance.transmission.user.clone().unwrap_or_default(),
-        password: instance.transmission.password.clone().unwrap_or_default(),
+        password: match &instance.transmission.password {
+            Some(secret) => secret.reveal()?,
+            None => String::new(),
+        },
     };
-    let mut client = TransClient::with_auth(url, basic_auth);
-    let all_torrents: Vec<Torrent> = client
-        .torrent_get(Torrent::request_fields(), None)
-        .await
-        .map_err(|e| anyhow!("Could not retrieve list of torrents: {}", e))?
-        .arguments
-        .torrents
-        .into_iter()
-        .map(Torrent::try_from)
-        .collect::<Result<_, anyhow::Error>>()?;
+    let mut client = TransClient::new_with_client(url, instance.transmission.tls.http_client()?);
+    client.set_auth(basic_auth);
+    let all_torrents = fetch_torrents(&mut client, &instance.transmission.rpc).await?;
+
+    let now = Utc::now();
+    for torrent in all_torrents.iter() {
+        state.store.observe(now, torrent);
+    }
 
-    let mut delete_ids_with_data: Vec<Id> = Default::default();
-    let mut delete_ids_without_data: Vec<Id> = Default::default();
+    let mut candidates: Vec<DeletionCandidate> = Default::default();
+    let mut matched: HashMap<String, usize> = instance
+        .policies
+        .iter()
+        .enumerate()
+        .map(|(index, policy)| (policy.name_or_index(index).into_owned(), 0))
+        .collect();
     let mut counts: HashMap<String, usize> = Default::default();
     let mut sizes: HashMap<String, usize> = Default::default();
-    for torrent in all_torrents {
+    let mut extremes: HashMap<String, (Option<chrono::Duration>, Option<f64>)> = instance
+        .policies
+        .iter()
+        .enumerate()
+        .map(|(index, policy)| (policy.name_or_index(index).into_owned(), (None, None)))
+        .collect();
+    let mut unmanaged: HashMap<(String, String), (usize, usize)> = Default::default();
+    let mut views: Vec<TorrentView> = Default::default();
+    for torrent in all_torrents.iter() {
+        let mut governed = None;
         for (index, policy) in instance.policies.iter().enumerate() {
-            let metrics_policy = Policy::new_for(
-                &instance.transmission.url,
-                policy.name_or_index(index).as_ref(),
-            );
-            let is_match = policy.applicable(&torrent).map(|a| a.matches());
+            let metrics_policy =
+                Policy::new_for(instance.name(), policy.name_or_index(index).as_ref());
+            let is_match = policy.applicable(torrent).map(|a| a.matches());
             if is_match.is_none() {
                 // This torrent is not interesting to us
                 continue;
             }
+            if let Some(decision) = is_match {
+                governed.get_or_insert((policy.name_or_index(index).into_owned(), decision));
+                state.store.record_decision(
+                    now,
+                    &torrent.hash,
+                    &policy.name_or_index(index),
+                    decision,
+                );
+            }
             counts
                 .entry(policy.name_or_index(index).into_owned())
                 .and_modify(|n| *n += 1)
@@ -94,114 +414,726 @@ async fn tick_on_instance(instance: &Instance, take_action: bool, metrics: &Metr
                 .and_modify(|n| *n += torrent.total_size)
                 .or_insert(torrent.total_size);
             metrics.track_size(&metrics_policy, torrent.total_size);
-            if let Some(true) = is_match.map(|cm| cm.is_match()) {
-                metrics.track_torrent_deletion(&metrics_policy);
+            let age = torrent.done_date.map(|done| now - done);
+            let ratio = torrent.upload_ratio as f64;
+            metrics.track_seeding(&metrics_policy, age, ratio);
+            let (oldest, lowest_ratio) = extremes
+                .entry(policy.name_or_index(index).into_owned())
+                .or_default();
+            *oldest = (*oldest).max(age);
+            *lowest_ratio = Some(lowest_ratio.map_or(ratio, |r| r.min(ratio)));
+            if let Some(reason) = is_match.filter(ConditionMatch::is_match) {
+                *matched
+                    .entry(policy.name_or_index(index).into_owned())
+                    .or_default() += 1;
                 info!(
                     torrent = ?torrent.name,
                     matched_policy = ?policy.name_or_index(index),
-                    ?take_action,
+                    dry_run = !take_action || instance.is_dry_run(policy),
                     delete_data = ?policy.delete_data,
                     "Matched torrent",
                 );
-
-                if policy.delete_data {
-                    delete_ids_with_data.push(Id::Hash(torrent.hash.to_string()));
-                } else {
-                    delete_ids_without_data.push(Id::Hash(torrent.hash.to_string()));
-                }
+                candidates.push(DeletionCandidate {
+                    torrent,
+                    policy_index: index,
+                    policy,
+                    reason,
+                });
             }
         }
+        if governed.is_none() {
+            let tracker = torrent
+                .trackers
+                .first()
+                .and_then(Url::host_str)
+                .unwrap_or_default()
+                .to_string();
+            let (count, size) = unmanaged
+                .entry((tracker, format!("{:?}", torrent.status)))
+                .or_default();
+            *count += 1;
+            *size += torrent.total_size;
+        }
+        views.push(TorrentView::new(torrent, governed));
     }
+    entry.update_status(|status| status.set_torrents(views));
     for (policy_name, count) in counts.iter() {
-        metrics.update_count(
-            &Policy::new_for(&instance.transmission.url, policy_name),
-            *count,
-        );
+        metrics.update_count(&Policy::new_for(instance.name(), policy_name), *count);
     }
     for (policy_name, size) in sizes.iter() {
-        metrics.update_size(
-            &Policy::new_for(&instance.transmission.url, policy_name),
-            *size,
+        metrics.update_size(&Policy::new_for(instance.name(), policy_name), *size);
+    }
+    metrics.update_unmanaged(instance.name(), &unmanaged);
+    let unmanaged_bytes: usize = unmanaged.values().map(|(_, size)| size).sum();
+    if let Some(threshold) = instance.transmission.warn_unmanaged_bytes {
+        if unmanaged_bytes as u64 > threshold {
+            warn!(
+                unmanaged_bytes,
+                threshold, "Torrents that no policy governs exceed the configured threshold",
+            );
+        }
+    }
+    for (policy_name, (oldest, lowest_ratio)) in extremes.iter() {
+        metrics.update_extremes(
+            &Policy::new_for(instance.name(), policy_name),
+            *oldest,
+            *lowest_ratio,
+        );
+    }
+    for (policy_name, count) in matched.iter() {
+        metrics.update_matched(&Policy::new_for(instance.name(), policy_name), *count);
+    }
+    for (index, policy) in instance.policies.iter().enumerate() {
+        metrics.update_dry_run(
+            &Policy::new_for(instance.name(), &policy.name_or_index(index)),
+            !take_action || instance.is_dry_run(policy),
         );
     }
 
+    // Torrents that stopped matching get counted again if they match
+    // again later:
+    let still_matching: HashSet<_> = candidates
+        .iter()
+        .map(|candidate| (candidate.torrent.hash.clone(), candidate.policy_name()))
+        .collect();
+    state
+        .dry_run_actions
+        .retain(|key| still_matching.contains(key));
+
+    // The circuit breaker judges everything that policies matched,
+    // before action windows and deletion limits hold some of it back,
+    // so that limits can't hide a policy that matches far too much.
+    // Policies that the config puts in dry-run mode don't count:
+    let matched_deletions: Vec<_> = candidates
+        .iter()
+        .filter(|candidate| !instance.is_dry_run(candidate.policy))
+        .cloned()
+        .collect();
+    let instance_window_open = instance
+        .transmission
+        .action_window
+        .is_none_or(|window| window.contains(now));
+    let (candidates, outside_window): (Vec<_>, Vec<_>) =
+        candidates.into_iter().partition(|candidate| {
+            instance_window_open
+                && candidate
+                    .policy
+                    .action_window
+                    .is_none_or(|window| window.contains(now))
+        });
+    // Policies that the config puts in dry-run mode don't use up
+    // deletion limits either:
+    let (candidates, observed): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|candidate| !instance.is_dry_run(candidate.policy));
+    let admission = state
+        .limiter
+        .admit(now, &instance.transmission.limits, candidates);
+    let mut deferred: HashMap<String, usize> = instance
+        .policies
+        .iter()
+        .enumerate()
+        .map(|(index, policy)| (policy.name_or_index(index).into_owned(), 0))
+        .collect();
+    for candidate in outside_window.iter() {
+        info!(
+            torrent = ?candidate.torrent.name,
+            matched_policy = ?candidate.policy_name(),
+            "Outside the action window, deferring to a later tick",
+        );
+        *deferred.entry(candidate.policy_name()).or_default() += 1;
+    }
+    for candidate in admission.deferred.iter() {
+        info!(
+            torrent = ?candidate.torrent.name,
+            matched_policy = ?candidate.policy_name(),
+            "Deletion limit reached, deferring to a later tick",
+        );
+        *deferred.entry(candidate.policy_name()).or_default() += 1;
+    }
+    for (policy_name, count) in deferred.iter() {
+        metrics.update_deferred(&Policy::new_for(instance.name(), policy_name), *count);
+    }
+
+    // Policies in dry-run mode don't delete anything, so the circuit
+    // breaker doesn't keep them from reporting what they would do:
+    let mut dry_run_reported = vec![];
+    for candidate in observed.iter() {
+        track_dry_run_action(state, metrics, instance, candidate);
+        dry_run_reported.push(ReportedAction::new(candidate, false));
+    }
     if take_action {
-        if !delete_ids_with_data.is_empty() {
-            info!(
-                torrents_to_delete = delete_ids_with_data.len(),
-                "Deleting data..."
-            );
-            client
-                .torrent_remove(delete_ids_with_data, true)
-                .await
-                .map_err(|e| anyhow!(e.to_string()))
-                .context("Deleting torrents with local data")?;
+        // What dry-run policies would have done gets its own report, so
+        // webhooks can tell it apart from actual deletions:
+        let report = TickReport::new(
+            instance.name(),
+            now,
+            true,
+            std::mem::take(&mut dry_run_reported),
+        );
+        notify_webhooks(instance, state, metrics, report);
+    }
+
+    let deleting = take_action && !admission.admitted.is_empty();
+    if let Err(trip) = state.breaker.check(
+        &instance.transmission.circuit_breaker,
+        &all_torrents,
+        &matched_deletions,
+        deleting,
+    ) {
+        metrics.track_circuit_breaker_trip(instance.name());
+        if opt.override_circuit_breaker {
+            warn!(%trip, ?take_action, "Circuit breaker tripped, but overridden on the command line");
+        } else {
+            let latched = state.breaker.tripped().is_some();
+            if deleting {
+                error!(
+                    %trip,
+                    latched,
+                    refused_deletions = admission.admitted.len(),
+                    "Circuit breaker tripped, refusing to delete anything on this instance. Fix the config and restart, or pass --override-circuit-breaker",
+                );
+            } else {
+                warn!(
+                    %trip,
+                    ?take_action,
+                    latched,
+                    "Circuit breaker tripped in a tick that wouldn't delete anything",
+                );
+            }
+            // Without -f, dry-run policies haven't reported yet:
+            let report = TickReport::new(instance.name(), now, true, dry_run_reported);
+            notify_webhooks(instance, state, metrics, report);
+            // An instance that refuses to delete anything isn't healthy,
+            // so this has to show up in readiness and tick metrics:
+            if latched {
+                return Err(anyhow!("Circuit breaker tripped: {trip}"));
+            }
+            status.succeed();
+            return Ok(());
         }
-        if !delete_ids_without_data.is_empty() {
-            info!(
-                torrents_to_delete = delete_ids_without_data.len(),
-                "Deleting torrents without data.."
+    }
+
+    let mut reported = vec![];
+    if take_action {
+        let (with_data, without_data): (Vec<_>, Vec<_>) = admission
+            .admitted
+            .into_iter()
+            .partition(|c| c.policy.delete_data);
+        for (batch, delete_data, message, context) in [
+            (
+                with_data,
+                true,
+                "Deleting data...",
+                "Deleting torrents with local data",
+            ),
+            (
+                without_data,
+                false,
+                "Deleting torrents without data..",
+                "Deleting torrent metadata alone",
+            ),
+        ] {
+            if batch.is_empty() {
+                continue;
+            }
+            info!(torrents_to_delete = batch.len(), "{message}");
+            // Removals aren't retried: one that timed out may still
+            // have happened, and anything left over gets picked up
+            // again in the next tick.
+            let rpc_timeout = instance
+                .transmission
+                .rpc
+                .timeout
+                .to_std()
+                .context("RPC timeout must not be negative")?;
+            let result = match time::timeout(
+                rpc_timeout,
+                client.torrent_remove(ids_of(&batch), delete_data),
+            )
+            .await
+            {
+                Ok(result) => result.map_err(|e| anyhow!(e.to_string())),
+                Err(_) => Err(anyhow!("Timed out after {rpc_timeout:?}")),
+            }
+            .context(context);
+            for candidate in batch.iter() {
+                state.store.record_action(
+                    now,
+                    &candidate.torrent.hash,
+                    &candidate.policy_name(),
+                    candidate.policy.action(),
+                    result.is_ok(),
+                );
+                if let Some(audit) = &state.audit {
+                    let record = AuditRecord::new(
+                        now,
+                        instance.name(),
+                        candidate,
+                        result.as_ref().err().map(|e| format!("{e:#}")),
+                    );
+                    if let Err(e) = audit.append(&record) {
+                        error!(error=%e, torrent=?candidate.torrent.name, "Could not write to audit log");
+                    }
+                }
+            }
+            reported.extend(
+                batch
+                    .iter()
+                    .map(|candidate| ReportedAction::new(candidate, result.is_ok())),
             );
-            client
-                .torrent_remove(delete_ids_without_data, true)
-                .await
-                .map_err(|e| anyhow!(e.to_string()))
-                .context("Deleting torrent metadata alone")?;
+            if let Err(e) = result {
+                let report = TickReport::new(instance.name(), now, false, reported);
+                notify_webhooks(instance, state, metrics, report);
+                return Err(e);
+            }
+            for candidate in batch.iter() {
+                let metrics_policy = Policy::new_for(instance.name(), &candidate.policy_name());
+                metrics.track_action(&metrics_policy, candidate.policy.action(), false);
+                metrics.track_torrent_deletion(&metrics_policy, candidate.reason.into());
+                metrics.track_deleted_bytes(&metrics_policy, candidate.torrent.total_size);
+            }
+            state.limiter.record(now, &batch);
+        }
+    } else {
+        for candidate in admission.admitted.iter() {
+            track_dry_run_action(state, metrics, instance, candidate);
+            reported.push(ReportedAction::new(candidate, false));
         }
+        reported.extend(dry_run_reported);
     }
+    let report = TickReport::new(instance.name(), now, !take_action, reported);
+    notify_webhooks(instance, state, metrics, report);
     status.succeed();
     Ok(())
 }
 
+/// An instance's tick loop, along with everything that needs to
+/// survive restarting the loop after a panic.
+struct InstanceLoop {
+    entry: Arc<InstanceEntry>,
+    requests: mpsc::Receiver<TickRequest>,
+    state: TickState,
+    next_tick: time::Instant,
+    consecutive_failures: u32,
+}
+
+impl InstanceLoop {
+    /// Runs scheduled and requested ticks until `shutdown` fires.
+    async fn run(&mut self, opt: &Opt, metrics: &Metrics, mut shutdown: watch::Receiver<()>) {
+        let entry = self.entry.clone();
+        let instance = &entry.instance;
+        let poll_interval = instance.transmission.poll_interval();
+        loop {
+            // Ticks only ever run here, so requested ticks can't
+            // overlap with scheduled ones. Shutdown goes first, so that
+            // no new tick starts once it was asked for:
+            let request = tokio::select! {
+                biased;
+                _ = shutdown.changed() => break,
+                _ = time::sleep_until(self.next_tick) => None,
+                Some(request) = self.requests.recv() => Some(request),
+            };
+            let take_action =
+                opt.take_action && !opt.dry_run && !request.as_ref().is_some_and(|r| r.dry_run);
+            debug!(
+                instance = instance.name(),
+                requested = request.is_some(),
+                ?take_action,
+                "Polling"
+            );
+            let result = tick_on_instance(&entry, opt, take_action, &mut self.state, metrics).await;
+            self.state.store.flush();
+            self.state.store.compact_if_due(Utc::now());
+            entry.update_status(|status| status.record_tick(Utc::now(), &result));
+            let requested = request.is_some();
+            if let Some(done) = request.and_then(|r| r.done) {
+                let _ = done.send(result.as_ref().map(|_| ()).map_err(|e| format!("{e:#}")));
+            }
+            if let Err(e) = result {
+                self.consecutive_failures += 1;
+                warn!(instance=instance.name(), error=%e, error_debug=?e, consecutive_failures=self.consecutive_failures, "Error polling");
+            } else {
+                self.consecutive_failures = 0;
+                debug!(instance = instance.name(), "Polling succeeded");
+            }
+            let delay = instance
+                .transmission
+                .rpc
+                .poll_delay(poll_interval, self.consecutive_failures);
+            metrics.update_backoff(instance.name(), self.consecutive_failures, delay);
+            if !requested {
+                if delay > poll_interval {
+                    info!(
+                        instance = instance.name(),
+                        ?delay,
+                        "Backing off after repeated failures"
+                    );
+                }
+                self.next_tick =
+                    (self.next_tick + delay.to_std().unwrap()).max(time::Instant::now());
+            }
+        }
+        self.state.store.flush();
+        debug!(instance = instance.name(), "Stopped");
+    }
+}
+
+/// Waits for SIGINT or, on unix, SIGTERM, and returns which one arrived.
+async fn shutdown_signal() -> io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+            _ = term.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "SIGINT")
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opt = Opt::parse();
+    init_logging();
+    match &opt.command {
+        Some(Command::Check {
+            config,
+            config_format,
+        }) => {
+            check(&load_config(config, *config_format)?);
+            return Ok(());
+        }
+        Some(Command::Export {
+            config,
+            config_format,
+            to,
+        }) => {
+            let exported = DeclarativeConfig::from_instances(load_config(config, *config_format)?)
+                .render(*to)
+                .map_err(|e| anyhow!("Could not export {config:?}: {e}"))?;
+            print!("{exported}");
+            return Ok(());
+        }
+        Some(Command::Lint {
+            config,
+            config_format,
+        }) => {
+            let findings = lint(&load_config(config, *config_format)?);
+            for finding in findings.iter() {
+                println!("{finding}");
+            }
+            let errors = findings
+                .iter()
+                .filter(|f| f.severity == Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(anyhow!("Config {config:?} has {errors} policy errors"));
+            }
+            return Ok(());
+        }
+        Some(Command::Schema) => {
+            println!("{}", serde_json::to_string_pretty(&json_schema())?);
+            return Ok(());
+        }
+        None => {}
+    }
+    let config = opt.config.clone().expect("clap requires a config file");
     let mut metrics_registry = Registry::default();
-    let metrics = Metrics::for_registry(&mut metrics_registry);
+    let defaults = BucketLayouts::default();
+    let buckets = BucketLayouts {
+        size: opt.size_buckets.clone().unwrap_or(defaults.size),
+        seeding_age: opt
+            .seeding_age_buckets
+            .clone()
+            .unwrap_or(defaults.seeding_age),
+        ratio: opt.ratio_buckets.clone().unwrap_or(defaults.ratio),
+    };
+    let metrics = Metrics::for_registry(&mut metrics_registry, &buckets);
 
-    init_logging();
-    // let instances = StarlarkConfig::configure(&opt.config)?;
-    let instances = configure(&opt.config).map_err(|e| anyhow!("{e}"))?;
+    // let instances = StarlarkConfig::configure(&config)?;
+    let instances = load_config(&config, opt.config_format)?;
+    for finding in lint(&instances) {
+        warn!(
+            instance = finding.instance,
+            policies = ?finding.policies,
+            severity = %finding.severity,
+            "{}",
+            finding.message
+        );
+    }
+    let audit = opt
+        .audit_log
+        .as_ref()
+        .map(|path| {
+            AuditLog::open(path)
+                .map(Arc::new)
+                .with_context(|| format!("Opening audit log {path:?}"))
+        })
+        .transpose()?;
+    // Bind early, so a bad address fails startup before any instance
+    // gets to do anything:
+    let listener = match opt.prometheus_listen_addr {
+        Some(addr) => Some((
+            addr,
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Could not listen on metrics address {addr}"))?,
+        )),
+        None => None,
+    };
+    let (entries, requests): (Vec<Arc<InstanceEntry>>, Vec<_>) =
+        instances.into_iter().map(InstanceEntry::new).unzip();
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
     let mut handles = JoinSet::new();
-    for instance in instances {
+    let deliveries = Deliveries::default();
+    for (entry, requests) in entries.iter().cloned().zip(requests) {
+        let instance = &entry.instance;
         info!(
-            instance=instance.transmission.url, poll_interval=?instance.transmission.poll_interval,
+            instance=instance.name(), poll_interval=?instance.transmission.poll_interval(),
             "Running"
         );
+        if instance.transmission.tls.insecure_skip_verify {
+            warn!(
+                instance = instance.name(),
+                "Not verifying the server's TLS certificate"
+            );
+        }
         let metrics = metrics.clone();
+        let opt = opt.clone();
+        let audit = audit.clone();
+        let store = match &opt.state_dir {
+            Some(dir) => StateStore::open(dir, instance.name())
+                .with_context(|| format!("Opening state store in {dir:?}"))?,
+            None => StateStore::in_memory(),
+        };
+        let instance_loop = Arc::new(tokio::sync::Mutex::new(InstanceLoop {
+            entry: entry.clone(),
+            requests,
+            state: TickState {
+                store,
+                audit,
+                deliveries: deliveries.clone(),
+                ..Default::default()
+            },
+            next_tick: time::Instant::now(),
+            consecutive_failures: 0,
+        }));
+        let shutdown = shutdown_rx.clone();
         handles.spawn(async move {
-            let mut ticker =
-                time::interval(instance.transmission.poll_interval.to_std().unwrap());
-            loop {
-                ticker.tick().await;
-                debug!(instance=instance.transmission.url, "Polling");
-                if let Err(e) = tick_on_instance(&instance, opt.take_action, &metrics).await {
-                    warn!(instance=instance.transmission.url, error=%e, error_debug=?e, "Error polling");
-                } else {
-                    debug!(instance=instance.transmission.url, "Polling succeeded");
+            supervisor::supervise(entry.instance.name(), &metrics, shutdown.clone(), || {
+                let (instance_loop, opt, metrics, shutdown) = (
+                    instance_loop.clone(),
+                    opt.clone(),
+                    metrics.clone(),
+                    shutdown.clone(),
+                );
+                async move {
+                    instance_loop
+                        .lock()
+                        .await
+                        .run(&opt, &metrics, shutdown)
+                        .await
+                }
+            })
+            .await;
+            Ok(())
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        let entries = entries.clone();
+        let mut usr1 =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+                .context("Installing SIGUSR1 handler")?;
+        tokio::spawn(async move {
+            while usr1.recv().await.is_some() {
+                info!("Received SIGUSR1, ticking on all instances");
+                for entry in entries.iter() {
+                    entry.request_tick();
                 }
             }
         });
     }
 
-    if let Some(addr) = opt.prometheus_listen_addr {
+    if let Some((addr, listener)) = listener {
+        let mut shutdown = shutdown_rx.clone();
+        let ready_poll_intervals = opt.ready_poll_intervals;
+        let api_allow_deletions = opt.api_allow_deletions;
         handles.spawn(async move {
-            let router = metrics::metrics_router(metrics_registry);
-            let listener = tokio::net::TcpListener::bind(addr)
+            let router = metrics::metrics_router(metrics_registry).merge(api::api_router(
+                entries,
+                ready_poll_intervals,
+                api_allow_deletions,
+            ));
+            axum::serve(listener, router)
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown.changed().await;
+                })
                 .await
-                .map_err(|e| format!("Could not listen on metrics address {:?}: {}", addr, e))
-                .expect("Listen on metrics address");
-            axum::serve(listener, router).await
         });
         info!(
             metrics_endpoint = format!("http://{}/metrics", addr),
-            "Serving prometheus metrics"
+            api_endpoint = format!("http://{}/api/instances", addr),
+            "Serving prometheus metrics and API"
         );
     }
-    // Any of these tasks returning is bad news:
-    if let Some(task) = handles.join_next().await {
-        task?.context("task exited prematurely")?;
-        anyhow::bail!("Task exited unexpectedly, but with a success?");
+    tokio::select! {
+        signal = shutdown_signal() => {
+            info!(signal = signal?, deadline = ?opt.shutdown_deadline, "Shutting down");
+        }
+        // Any of these tasks returning before shutdown is bad news:
+        Some(task) = handles.join_next() => {
+            task?.context("task exited prematurely")?;
+            anyhow::bail!("Task exited unexpectedly, but with a success?");
+        }
+    }
+
+    // Instance loops stop once their current tick is done, and the
+    // webhooks get to hear about what those ticks did:
+    shutdown_tx.send_replace(());
+    let finished = time::timeout(opt.shutdown_deadline, async {
+        while let Some(task) = handles.join_next().await {
+            task?.context("task failed while shutting down")?;
+        }
+        deliveries.drain().await;
+        anyhow::Ok(())
+    })
+    .await;
+    match finished {
+        Ok(result) => result?,
+        Err(_) => {
+            handles.abort_all();
+            while handles.join_next().await.is_some() {}
+            anyhow::bail!(
+                "Running ticks and webhook deliveries did not finish within {:?}, exiting anyway",
+                opt.shutdown_deadline
+            );
+        }
     }
+    info!("Shut down");
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves a transmission RPC endpoint that fails the first
+    /// `failures` requests and reports no torrents afterwards.
+    async fn stand_in(failures: usize) -> (Url, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/transmission/rpc",
+                post(move |State(requests): State<Arc<AtomicUsize>>| async move {
+                    if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                        return (StatusCode::INTERNAL_SERVER_ERROR, String::new());
+                    }
+                    (
+                        StatusCode::OK,
+                        r#"{"arguments": {"torrents": []}, "result": "success"}"#.to_string(),
+                    )
+                }),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/transmission/rpc", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url.parse().unwrap(), requests)
+    }
+
+    #[tokio::test]
+    async fn retries_fetching_torrents() {
+        let (url, requests) = stand_in(1).await;
+        let mut client = TransClient::new(url);
+        let torrents = fetch_torrents(&mut client, &RpcSettings::default())
+            .await
+            .unwrap();
+        assert!(torrents.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn counts_dry_run_actions_once() {
+        use gearbox_maintenance::config::{
+            policy::{Condition, DeletePolicy, PolicyMatch},
+            transmission::Transmission,
+        };
+        use prometheus_client::encoding::text::encode;
+        use transmission_rpc::types::{ErrorType, TorrentStatus};
+
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let policy = DeletePolicy::new_real(
+            "p",
+            PolicyMatch::default(),
+            Condition::new().unwrap().with_max_ratio(1.0),
+        )
+        .unwrap();
+        let instance = Instance {
+            transmission: Transmission::new("http://seedbox"),
+            policies: vec![policy.clone()],
+            inherited: Default::default(),
+        };
+        let torrent = Torrent {
+            id: 1,
+            hash: "abcd".to_string(),
+            name: "testcase".to_string(),
+            done_date: Some(Utc::now()),
+            error: ErrorType::Ok,
+            error_string: "".to_string(),
+            upload_ratio: 2.0,
+            computed_upload_ratio: 2.0,
+            uploaded_ever: 200,
+            status: TorrentStatus::Seeding,
+            num_files: 1,
+            total_size: 100,
+            trackers: vec![],
+        };
+        let candidate = DeletionCandidate {
+            torrent: &torrent,
+            policy_index: 0,
+            policy: &policy,
+            reason: ConditionMatch::Ratio(2.0),
+        };
+        let counted = |registry: &Registry| {
+            let mut output = String::new();
+            encode(&mut output, registry).unwrap();
+            output
+                .lines()
+                .find(|line| {
+                    line.starts_with("torrent_action_total{") && line.contains(r#"dry_run="true""#)
+                })
+                .map(|line| line.rsplit(' ').next().unwrap().to_string())
+        };
+
+        let mut state = TickState::default();
+        track_dry_run_action(&mut state, &metrics, &instance, &candidate);
+        track_dry_run_action(&mut state, &metrics, &instance, &candidate);
+        assert_eq!(counted(&registry).as_deref(), Some("1"));
+
+        // Once the torrent stopped matching, it counts again:
+        state.dry_run_actions.clear();
+        track_dry_run_action(&mut state, &metrics, &instance, &candidate);
+        assert_eq!(counted(&registry).as_deref(), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_fetching_torrents() {
+        let (url, requests) = stand_in(usize::MAX).await;
+        let mut client = TransClient::new(url);
+        let rpc = RpcSettings {
+            retries: 1,
+            ..Default::default()
+        };
+        assert!(fetch_torrents(&mut client, &rpc).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,4 +1,11 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::atomic::AtomicU64,
+    sync::Arc,
+    time::SystemTime,
+};
 
 use axum::{
     body::Body,
@@ -8,21 +15,98 @@ use axum::{
     routing::get,
     Router,
 };
+use chrono::Utc;
+use gearbox_maintenance::config::policy::{Action, ConditionMatchKind};
 use prometheus_client::{
     encoding::{text::encode, EncodeLabelSet},
     metrics::{
         counter::Counter,
-        family::Family,
+        family::{Family, MetricConstructor},
         gauge::Gauge,
-        histogram::{exponential_buckets, Histogram},
+        histogram::{exponential_buckets, linear_buckets, Histogram},
     },
     registry::Registry,
 };
 use tokio::sync::Mutex;
 
+/// The bucket boundaries of a histogram.
+///
+/// Parsed from `exponential:START,FACTOR,COUNT`,
+/// `linear:START,WIDTH,COUNT` or a plain comma-separated list of
+/// boundaries.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BucketLayout(Vec<f64>);
+
+impl FromStr for BucketLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, args) = s.split_once(':').unwrap_or(("explicit", s));
+        let args = args
+            .split(',')
+            .map(|arg| arg.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("Invalid bucket layout {s:?}: {e}"))?;
+        let buckets: Vec<f64> = match (kind, &args[..]) {
+            ("exponential", [start, factor, count]) => {
+                exponential_buckets(*start, *factor, *count as u16).collect()
+            }
+            ("linear", [start, width, count]) => {
+                linear_buckets(*start, *width, *count as u16).collect()
+            }
+            ("explicit", buckets) => buckets.to_vec(),
+            _ => {
+                return Err(format!(
+                    "Invalid bucket layout {s:?}: expected exponential:START,FACTOR,COUNT, linear:START,WIDTH,COUNT or a list of boundaries"
+                ))
+            }
+        };
+        if buckets.is_empty() || buckets.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!(
+                "Invalid bucket layout {s:?}: boundaries must be increasing"
+            ));
+        }
+        Ok(BucketLayout(buckets))
+    }
+}
+
+impl fmt::Display for BucketLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let boundaries: Vec<String> = self.0.iter().map(f64::to_string).collect();
+        write!(f, "{}", boundaries.join(","))
+    }
+}
+
+impl MetricConstructor<Histogram> for BucketLayout {
+    fn new_metric(&self) -> Histogram {
+        Histogram::new(self.0.iter().copied())
+    }
+}
+
+/// Bucket layouts for the histograms that describe torrents.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BucketLayouts {
+    /// Torrent sizes, in bytes.
+    pub(crate) size: BucketLayout,
+    /// How long torrents have been seeding, in seconds.
+    pub(crate) seeding_age: BucketLayout,
+    /// Upload ratios.
+    pub(crate) ratio: BucketLayout,
+}
+
+impl Default for BucketLayouts {
+    fn default() -> Self {
+        BucketLayouts {
+            size: BucketLayout(exponential_buckets(5e9, 2.0, 11).collect()),
+            seeding_age: BucketLayout(exponential_buckets(3600.0, 2.0, 12).collect()),
+            ratio: BucketLayout(vec![0.1, 0.25, 0.5, 1.0, 1.5, 2.0, 3.0, 5.0, 10.0]),
+        }
+    }
+}
+
 pub(crate) struct TickDurationHandle {
-    family: Family<TransmissionLocation, Histogram>,
-    variant: TransmissionLocation,
+    family: Family<InstanceName, Histogram>,
+    variant: InstanceName,
     started: SystemTime,
 }
 
@@ -37,14 +121,18 @@ impl Drop for TickDurationHandle {
 }
 
 pub(crate) struct FailureCountHandle {
-    family: Family<TransmissionLocation, Counter>,
-    variant: TransmissionLocation,
+    family: Family<InstanceName, Counter>,
+    last_success: Family<InstanceName, Gauge>,
+    variant: InstanceName,
     success: bool,
 }
 
 impl FailureCountHandle {
     pub fn succeed(mut self) {
         self.success = true;
+        self.last_success
+            .get_or_create(&self.variant)
+            .set(Utc::now().timestamp());
     }
 }
 
@@ -56,8 +144,12 @@ impl Drop for FailureCountHandle {
     }
 }
 
+/// Labels metrics with an instance's name. The label keeps the key
+/// `transmission_url` that it had before instances had names, so that
+/// dashboards and alerts keep working; instances without a name are
+/// labeled with their URL, like before.
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
-struct TransmissionLocation {
+struct InstanceName {
     transmission_url: String,
 }
 
@@ -68,38 +160,140 @@ pub(crate) struct Policy {
 }
 
 impl Policy {
-    pub(crate) fn new_for(transmission_url: &str, policy: &str) -> Self {
+    pub(crate) fn new_for(instance_name: &str, policy: &str) -> Self {
         Policy {
-            transmission_url: transmission_url.to_string(),
+            transmission_url: instance_name.to_string(),
             policy: policy.to_string(),
         }
     }
 }
 
+/// The policy name that torrents no policy governs are reported under.
+pub(crate) const UNMANAGED: &str = "unmanaged";
+
+/// A group of torrents on an instance: either those governed by a
+/// policy, or the unmanaged ones on one tracker host in one status.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct Population {
+    transmission_url: String,
+    policy: String,
+    tracker: String,
+    status: String,
+}
+
+impl Population {
+    fn governed(policy: &Policy) -> Self {
+        Population {
+            transmission_url: policy.transmission_url.clone(),
+            policy: policy.policy.clone(),
+            tracker: String::new(),
+            status: String::new(),
+        }
+    }
+
+    fn unmanaged(instance_name: &str, tracker: &str, status: &str) -> Self {
+        Population {
+            transmission_url: instance_name.to_string(),
+            policy: UNMANAGED.to_string(),
+            tracker: tracker.to_string(),
+            status: status.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DeletionReason {
+    transmission_url: String,
+    policy: String,
+    reason: &'static str,
+}
+
+impl DeletionReason {
+    fn new(policy: &Policy, kind: ConditionMatchKind) -> Self {
+        DeletionReason {
+            transmission_url: policy.transmission_url.clone(),
+            policy: policy.policy.clone(),
+            reason: match kind {
+                ConditionMatchKind::None => "none",
+                ConditionMatchKind::Ratio => "ratio",
+                ConditionMatchKind::SeedTime => "seed_time",
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ActionTaken {
+    transmission_url: String,
+    policy: String,
+    action: String,
+    dry_run: bool,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct WebhookDelivery {
+    transmission_url: String,
+    outcome: &'static str,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Metrics {
-    tick_duration: Family<TransmissionLocation, Histogram>,
-    tick_failure_counter: Family<TransmissionLocation, Counter>,
-    size_distribution: Family<Policy, Histogram>,
-    torrent_deletions: Family<Policy, Counter>,
-    total_count: Family<Policy, Gauge>,
-    total_size: Family<Policy, Gauge>,
+    tick_duration: Family<InstanceName, Histogram>,
+    tick_failure_counter: Family<InstanceName, Counter>,
+    last_tick_attempt: Family<InstanceName, Gauge>,
+    last_tick_success: Family<InstanceName, Gauge>,
+    consecutive_failures: Family<InstanceName, Gauge>,
+    task_panics: Family<InstanceName, Counter>,
+    poll_delay: Family<InstanceName, Gauge>,
+    size_distribution: Family<Policy, Histogram, BucketLayout>,
+    seeding_age_distribution: Family<Policy, Histogram, BucketLayout>,
+    ratio_distribution: Family<Policy, Histogram, BucketLayout>,
+    oldest_seeding_age: Family<Policy, Gauge>,
+    lowest_ratio: Family<Policy, Gauge<f64, AtomicU64>>,
+    torrent_deletions: Family<DeletionReason, Counter>,
+    deleted_bytes: Family<Policy, Counter>,
+    matched_count: Family<Policy, Gauge>,
+    dry_run: Family<Policy, Gauge>,
+    actions: Family<ActionTaken, Counter>,
+    total_count: Family<Population, Gauge>,
+    total_size: Family<Population, Gauge>,
+    /// The unmanaged series reported in each instance's last tick, so
+    /// that those which no longer apply can be dropped.
+    unmanaged_series: Arc<std::sync::Mutex<HashMap<String, HashSet<Population>>>>,
+    deferred_count: Family<Policy, Gauge>,
+    circuit_breaker_trips: Family<InstanceName, Counter>,
+    webhook_deliveries: Family<WebhookDelivery, Counter>,
 }
 
 impl Metrics {
     /// Initialize and return a set of metrics registered on `registry`.
-    pub(crate) fn for_registry(registry: &mut Registry) -> Self {
+    pub(crate) fn for_registry(registry: &mut Registry, buckets: &BucketLayouts) -> Self {
         let metrics = Self {
             tick_duration: Family::new_with_constructor(|| {
                 Histogram::new(exponential_buckets(1.0, 1.5, 20))
             }),
             tick_failure_counter: Family::default(),
-            size_distribution: Family::new_with_constructor(|| {
-                Histogram::new(exponential_buckets(5e9, 2.0, 11))
-            }),
+            last_tick_attempt: Family::default(),
+            last_tick_success: Family::default(),
+            consecutive_failures: Family::default(),
+            task_panics: Family::default(),
+            poll_delay: Family::default(),
+            size_distribution: Family::new_with_constructor(buckets.size.clone()),
+            seeding_age_distribution: Family::new_with_constructor(buckets.seeding_age.clone()),
+            ratio_distribution: Family::new_with_constructor(buckets.ratio.clone()),
+            oldest_seeding_age: Family::default(),
+            lowest_ratio: Family::default(),
             torrent_deletions: Family::default(),
+            deleted_bytes: Family::default(),
+            matched_count: Family::default(),
+            dry_run: Family::default(),
+            actions: Family::default(),
             total_count: Family::default(),
             total_size: Family::default(),
+            unmanaged_series: Default::default(),
+            deferred_count: Family::default(),
+            circuit_breaker_trips: Family::default(),
+            webhook_deliveries: Family::default(),
         };
         registry.register(
             "instance_fetch_duration_ms",
@@ -111,48 +305,134 @@ impl Metrics {
             "Number of times that fetching from the instance failed",
             metrics.tick_failure_counter.clone(),
         );
+        registry.register(
+            "instance_last_tick_timestamp_seconds",
+            "Unix timestamp of the last attempted tick on an instance",
+            metrics.last_tick_attempt.clone(),
+        );
+        registry.register(
+            "instance_last_success_timestamp_seconds",
+            "Unix timestamp of the last successful tick on an instance",
+            metrics.last_tick_success.clone(),
+        );
+        registry.register(
+            "instance_consecutive_failures",
+            "Number of ticks in a row that failed on an instance",
+            metrics.consecutive_failures.clone(),
+        );
+        registry.register(
+            "instance_task_panic_count",
+            "Number of times that an instance's task panicked and got restarted",
+            metrics.task_panics.clone(),
+        );
+        registry.register(
+            "instance_poll_delay_seconds",
+            "Time until the next scheduled tick on an instance; longer than the poll interval while backing off after failures",
+            metrics.poll_delay.clone(),
+        );
         registry.register(
             "torrent_size_bytes_historam",
             "Histogram of torrent size managed by policy.",
             metrics.size_distribution.clone(),
         );
+        registry.register(
+            "torrent_seeding_age_seconds_histogram",
+            "Histogram of how long torrents managed by a policy have been seeding.",
+            metrics.seeding_age_distribution.clone(),
+        );
+        registry.register(
+            "torrent_upload_ratio_histogram",
+            "Histogram of the upload ratio of torrents managed by a policy.",
+            metrics.ratio_distribution.clone(),
+        );
+        registry.register(
+            "torrent_oldest_seeding_age_seconds",
+            "How long the longest-seeding torrent managed by a policy has been seeding.",
+            metrics.oldest_seeding_age.clone(),
+        );
+        registry.register(
+            "torrent_lowest_upload_ratio",
+            "The lowest upload ratio among torrents managed by a policy.",
+            metrics.lowest_ratio.clone(),
+        );
         registry.register(
             "torrent_deletion_count",
-            "Number of torrents that got deleted, per instance/policy",
+            "Deprecated, use torrent_action_total. Number of torrents that got deleted, per instance/policy and the reason they matched",
             metrics.torrent_deletions.clone(),
         );
+        registry.register(
+            "torrent_matched",
+            "Number of torrents that currently qualify for deletion, per instance/policy",
+            metrics.matched_count.clone(),
+        );
+        registry.register(
+            "policy_dry_run",
+            "Whether a policy only reports what it would delete (1) or deletes torrents (0), per instance/policy",
+            metrics.dry_run.clone(),
+        );
+        registry.register(
+            "torrent_action",
+            "Number of actions taken on torrents, per instance/policy/action; with dry_run=\"true\", the torrents that started to qualify for an action that a dry run didn't take",
+            metrics.actions.clone(),
+        );
+        registry.register(
+            "torrent_deleted_bytes",
+            "Total size of the torrents that got deleted, per instance/policy",
+            metrics.deleted_bytes.clone(),
+        );
         registry.register(
             "torrent_count",
-            "Number of torrents, per transmission URL and policy.",
+            "Number of torrents, per instance and policy; torrents that no policy governs are counted under policy=\"unmanaged\", per tracker host and status.",
             metrics.total_count.clone(),
         );
         registry.register(
             "torrent_size_bytes",
-            "Total data size of torrents in bytes, per transmission URL and policy.",
+            "Total data size of torrents in bytes, per instance and policy; torrents that no policy governs are counted under policy=\"unmanaged\", per tracker host and status.",
             metrics.total_size.clone(),
         );
+        registry.register(
+            "torrent_deletion_deferred",
+            "Number of deletion candidates deferred by deletion limits in the last tick, per instance/policy",
+            metrics.deferred_count.clone(),
+        );
+        registry.register(
+            "circuit_breaker_tripped_count",
+            "Number of ticks in which the safety circuit breaker refused to delete torrents, per instance",
+            metrics.circuit_breaker_trips.clone(),
+        );
+        registry.register(
+            "webhook_delivery_count",
+            "Number of webhook deliveries, per instance and outcome (success or failure)",
+            metrics.webhook_deliveries.clone(),
+        );
 
         metrics
     }
 
     /// Return a histogram timer that tracks the duration it is in scope.
-    pub(crate) fn tick_duration(&self, url: &str) -> TickDurationHandle {
+    pub(crate) fn tick_duration(&self, instance: &str) -> TickDurationHandle {
         TickDurationHandle {
             family: self.tick_duration.clone(),
-            variant: TransmissionLocation {
-                transmission_url: url.to_string(),
+            variant: InstanceName {
+                transmission_url: instance.to_string(),
             },
             started: SystemTime::now(),
         }
     }
 
-    /// Return a [`FailureTracker`] that will record a failure when it goes out of scope.
-    pub(crate) fn tick_failure_tracker(&self, url: &str) -> FailureCountHandle {
+    /// Return a [`FailureTracker`] that will record a failure when it
+    /// goes out of scope, and records the tick attempt right away.
+    pub(crate) fn tick_failure_tracker(&self, instance: &str) -> FailureCountHandle {
+        let variant = InstanceName {
+            transmission_url: instance.to_string(),
+        };
+        self.last_tick_attempt
+            .get_or_create(&variant)
+            .set(Utc::now().timestamp());
         FailureCountHandle {
             family: self.tick_failure_counter.clone(),
-            variant: TransmissionLocation {
-                transmission_url: url.to_string(),
-            },
+            last_success: self.last_tick_success.clone(),
+            variant,
             success: false,
         }
     }
@@ -164,17 +444,168 @@ impl Metrics {
             .observe(size as f64);
     }
 
-    /// Track a torrent deletion.
-    pub(crate) fn track_torrent_deletion(&self, policy: &Policy) {
-        self.torrent_deletions.get_or_create(policy).inc();
+    /// Track a torrent's seeding age (if it has finished
+    /// downloading) and upload ratio on their distribution histograms.
+    pub(crate) fn track_seeding(&self, policy: &Policy, age: Option<chrono::Duration>, ratio: f64) {
+        if let Some(age) = age {
+            self.seeding_age_distribution
+                .get_or_create(policy)
+                .observe(age.num_seconds() as f64);
+        }
+        self.ratio_distribution.get_or_create(policy).observe(ratio);
+    }
+
+    /// Update the oldest seeding age and lowest ratio among torrents
+    /// managed by `policy`, or drop them if it manages none.
+    pub(crate) fn update_extremes(
+        &self,
+        policy: &Policy,
+        oldest: Option<chrono::Duration>,
+        lowest_ratio: Option<f64>,
+    ) {
+        match oldest {
+            Some(age) => {
+                self.oldest_seeding_age
+                    .get_or_create(policy)
+                    .set(age.num_seconds());
+            }
+            None => {
+                self.oldest_seeding_age.remove(policy);
+            }
+        }
+        match lowest_ratio {
+            Some(ratio) => {
+                self.lowest_ratio.get_or_create(policy).set(ratio);
+            }
+            None => {
+                self.lowest_ratio.remove(policy);
+            }
+        }
+    }
+
+    /// Track a torrent deletion, and why the torrent matched.
+    pub(crate) fn track_torrent_deletion(&self, policy: &Policy, kind: ConditionMatchKind) {
+        self.torrent_deletions
+            .get_or_create(&DeletionReason::new(policy, kind))
+            .inc();
+    }
+
+    /// Track an action that was executed successfully (or, in a dry
+    /// run, that would have been executed).
+    pub(crate) fn track_action(&self, policy: &Policy, action: Action, dry_run: bool) {
+        self.actions
+            .get_or_create(&ActionTaken {
+                transmission_url: policy.transmission_url.clone(),
+                policy: policy.policy.clone(),
+                action: action.to_string(),
+                dry_run,
+            })
+            .inc();
+    }
+
+    pub(crate) fn update_matched(&self, policy: &Policy, count: usize) {
+        self.matched_count.get_or_create(policy).set(count as i64);
+    }
+
+    pub(crate) fn update_dry_run(&self, policy: &Policy, dry_run: bool) {
+        self.dry_run.get_or_create(policy).set(dry_run as i64);
+    }
+
+    /// Track the size of a torrent that got deleted.
+    pub(crate) fn track_deleted_bytes(&self, policy: &Policy, size: usize) {
+        self.deleted_bytes.get_or_create(policy).inc_by(size as u64);
     }
 
     pub(crate) fn update_count(&self, policy: &Policy, count: usize) {
-        self.total_count.get_or_create(policy).set(count as i64);
+        self.total_count
+            .get_or_create(&Population::governed(policy))
+            .set(count as i64);
     }
 
     pub(crate) fn update_size(&self, policy: &Policy, size: usize) {
-        self.total_size.get_or_create(policy).set(size as i64);
+        self.total_size
+            .get_or_create(&Population::governed(policy))
+            .set(size as i64);
+    }
+
+    /// Update the count and size of the torrents on an instance that
+    /// no policy governs, keyed by tracker host and status. Series
+    /// from earlier ticks that have no torrents anymore get dropped.
+    pub(crate) fn update_unmanaged(
+        &self,
+        instance_name: &str,
+        unmanaged: &HashMap<(String, String), (usize, usize)>,
+    ) {
+        let current: HashSet<Population> = unmanaged
+            .iter()
+            .map(|((tracker, status), (count, size))| {
+                let labels = Population::unmanaged(instance_name, tracker, status);
+                self.total_count.get_or_create(&labels).set(*count as i64);
+                self.total_size.get_or_create(&labels).set(*size as i64);
+                labels
+            })
+            .collect();
+        let mut series = self
+            .unmanaged_series
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let previous = series.insert(instance_name.to_string(), current.clone());
+        for stale in previous.iter().flatten() {
+            if !current.contains(stale) {
+                self.total_count.remove(stale);
+                self.total_size.remove(stale);
+            }
+        }
+    }
+
+    pub(crate) fn update_deferred(&self, policy: &Policy, count: usize) {
+        self.deferred_count.get_or_create(policy).set(count as i64);
+    }
+
+    /// Update the backoff state of an instance after a tick.
+    pub(crate) fn update_backoff(
+        &self,
+        instance: &str,
+        consecutive_failures: u32,
+        delay: chrono::Duration,
+    ) {
+        let labels = InstanceName {
+            transmission_url: instance.to_string(),
+        };
+        self.consecutive_failures
+            .get_or_create(&labels)
+            .set(consecutive_failures.into());
+        self.poll_delay
+            .get_or_create(&labels)
+            .set(delay.num_seconds());
+    }
+
+    /// Track a panic in an instance's task.
+    pub(crate) fn track_panic(&self, instance: &str) {
+        self.task_panics
+            .get_or_create(&InstanceName {
+                transmission_url: instance.to_string(),
+            })
+            .inc();
+    }
+
+    /// Track a tick in which the circuit breaker refused deletions.
+    pub(crate) fn track_circuit_breaker_trip(&self, instance: &str) {
+        self.circuit_breaker_trips
+            .get_or_create(&InstanceName {
+                transmission_url: instance.to_string(),
+            })
+            .inc();
+    }
+
+    /// Track the outcome of delivering a tick report to a webhook.
+    pub(crate) fn track_webhook_delivery(&self, instance: &str, success: bool) {
+        self.webhook_deliveries
+            .get_or_create(&WebhookDelivery {
+                transmission_url: instance.to_string(),
+                outcome: if success { "success" } else { "failure" },
+            })
+            .inc();
     }
 }
 
@@ -204,3 +635,82 @@ pub(crate) fn metrics_router(registry: Registry) -> Router {
         .route("/metrics", get(metrics_handler))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tracks_reasons_and_timestamps() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let policy = Policy::new_for("seedbox", "ratio");
+        metrics.track_torrent_deletion(&policy, ConditionMatchKind::SeedTime);
+        metrics.track_deleted_bytes(&policy, 1000);
+        metrics.tick_failure_tracker("seedbox").succeed();
+        metrics.track_action(&policy, Action::RemoveWithData, false);
+        metrics.track_action(&policy, Action::Remove, true);
+        metrics.update_matched(&policy, 3);
+
+        let mut output = String::new();
+        encode(&mut output, &registry).unwrap();
+        assert!(output.contains(
+            r#"torrent_deletion_count_total{transmission_url="seedbox",policy="ratio",reason="seed_time"} 1"#
+        ));
+        assert!(output.contains(
+            r#"torrent_deleted_bytes_total{transmission_url="seedbox",policy="ratio"} 1000"#
+        ));
+        assert!(
+            output.contains(r#"instance_last_tick_timestamp_seconds{transmission_url="seedbox"}"#)
+        );
+        assert!(output
+            .contains(r#"instance_last_success_timestamp_seconds{transmission_url="seedbox"}"#));
+        assert!(
+            !output.contains(r#"instance_fetch_failure_count_total{transmission_url="seedbox"}"#)
+        );
+    }
+
+    #[test]
+    fn replaces_unmanaged_series() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let key = |tracker: &str, status: &str| (tracker.to_string(), status.to_string());
+        metrics.update_unmanaged(
+            "seedbox",
+            &HashMap::from([
+                (key("a.example", "Seeding"), (2, 300)),
+                (key("b.example", "Stopped"), (1, 100)),
+            ]),
+        );
+        metrics.update_unmanaged(
+            "seedbox",
+            &HashMap::from([(key("a.example", "Seeding"), (1, 200))]),
+        );
+
+        let mut output = String::new();
+        encode(&mut output, &registry).unwrap();
+        assert!(output.contains(
+            r#"torrent_count{transmission_url="seedbox",policy="unmanaged",tracker="a.example",status="Seeding"} 1"#
+        ));
+        assert!(output.contains(
+            r#"torrent_size_bytes{transmission_url="seedbox",policy="unmanaged",tracker="a.example",status="Seeding"} 200"#
+        ));
+        assert!(!output.contains("b.example"));
+    }
+
+    #[test]
+    fn parses_bucket_layouts() {
+        assert_eq!(
+            "exponential:1,2,3".parse(),
+            Ok(BucketLayout(vec![1.0, 2.0, 4.0]))
+        );
+        assert_eq!(
+            "linear:0.5,0.5,3".parse(),
+            Ok(BucketLayout(vec![0.5, 1.0, 1.5]))
+        );
+        assert_eq!("1, 5,10".parse(), Ok(BucketLayout(vec![1.0, 5.0, 10.0])));
+        assert!("10,5".parse::<BucketLayout>().is_err());
+        assert!("cubic:1,2,3".parse::<BucketLayout>().is_err());
+        assert!("linear:1,2".parse::<BucketLayout>().is_err());
+    }
+}
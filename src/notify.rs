@@ -0,0 +1,313 @@
+//! Delivery of tick summaries to [`Webhook`]s.
+//!
+//! By default, a webhook receives the [`TickReport`] as JSON. If the
+//! webhook has a template, the template is sent instead, with these
+//! placeholders filled in:
+//!
+//! * `{{instance}}`, `{{summary}}`, `{{count}}`, `{{bytes}}` and
+//!   `{{dry_run}}` are replaced with their values, escaped so they
+//!   can be used inside a JSON string.
+//! * `{{actions}}` and `{{report}}` are replaced with the JSON of the
+//!   list of actions and of the whole report, respectively.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::config::{
+    policy::{Action, ConditionMatch},
+    webhook::Webhook,
+};
+use crate::limits::DeletionCandidate;
+
+/// How long to wait before the first retry; each further retry waits twice as long.
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Webhook deliveries that are still in flight, so that shutting down
+/// can wait for them.
+#[derive(Clone, Debug, Default)]
+pub struct Deliveries(Arc<Mutex<JoinSet<()>>>);
+
+impl Deliveries {
+    /// Runs `delivery` in the background.
+    pub fn spawn(&self, delivery: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.0.lock().unwrap();
+        // Forget the finished ones, so the set doesn't grow forever:
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(delivery);
+    }
+
+    /// Waits for the deliveries that are in flight. If this gets
+    /// dropped before they are done, they get aborted.
+    pub async fn drain(&self) {
+        let mut tasks = std::mem::take(&mut *self.0.lock().unwrap());
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// An action that was taken (or planned, in a dry run) on a torrent.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ReportedAction {
+    pub policy: String,
+    pub hash: String,
+    pub name: String,
+    pub action: Action,
+    pub total_size: usize,
+    pub reason: ConditionMatch,
+    /// Whether transmission accepted the request; always false in a dry run.
+    pub success: bool,
+}
+
+impl ReportedAction {
+    pub fn new(candidate: &DeletionCandidate<'_>, success: bool) -> Self {
+        ReportedAction {
+            policy: candidate.policy_name(),
+            hash: candidate.torrent.hash.clone(),
+            name: candidate.torrent.name.clone(),
+            action: candidate.policy.action(),
+            total_size: candidate.torrent.total_size,
+            reason: candidate.reason,
+            success,
+        }
+    }
+}
+
+/// What happened in one tick on an instance.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct TickReport {
+    pub instance: String,
+    pub timestamp: DateTime<Utc>,
+    pub dry_run: bool,
+    pub actions: Vec<ReportedAction>,
+    pub summary: String,
+}
+
+impl TickReport {
+    pub fn new(
+        instance: &str,
+        timestamp: DateTime<Utc>,
+        dry_run: bool,
+        actions: Vec<ReportedAction>,
+    ) -> Self {
+        let bytes: usize = actions.iter().map(|a| a.total_size).sum();
+        let failed = actions.iter().filter(|a| !a.success).count();
+        let summary = if dry_run {
+            format!(
+                "Would remove {} torrents ({bytes} bytes) on {instance}",
+                actions.len()
+            )
+        } else if failed > 0 {
+            format!(
+                "Removed {} torrents ({bytes} bytes) on {instance}, {failed} failed",
+                actions.len() - failed
+            )
+        } else {
+            format!(
+                "Removed {} torrents ({bytes} bytes) on {instance}",
+                actions.len()
+            )
+        };
+        TickReport {
+            instance: instance.to_string(),
+            timestamp,
+            dry_run,
+            actions,
+            summary,
+        }
+    }
+
+    /// Returns true if `webhook` wants to hear about this report.
+    pub fn is_wanted_by(&self, webhook: &Webhook) -> bool {
+        !self.actions.is_empty() && (!self.dry_run || webhook.include_dry_run)
+    }
+
+    /// Renders the request body for `webhook`.
+    pub fn render(&self, webhook: &Webhook) -> anyhow::Result<String> {
+        let Some(template) = &webhook.template else {
+            return Ok(serde_json::to_string(self)?);
+        };
+        let escape = |s: &str| -> anyhow::Result<String> {
+            let quoted = serde_json::to_string(s)?;
+            Ok(quoted[1..quoted.len() - 1].to_string())
+        };
+        let bytes: usize = self.actions.iter().map(|a| a.total_size).sum();
+        Ok(template
+            .replace("{{instance}}", &escape(&self.instance)?)
+            .replace("{{summary}}", &escape(&self.summary)?)
+            .replace("{{count}}", &self.actions.len().to_string())
+            .replace("{{bytes}}", &bytes.to_string())
+            .replace("{{dry_run}}", &self.dry_run.to_string())
+            .replace("{{actions}}", &serde_json::to_string(&self.actions)?)
+            .replace("{{report}}", &serde_json::to_string(self)?))
+    }
+}
+
+/// POSTs `report` to `webhook`, retrying with exponential backoff.
+pub async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    report: &TickReport,
+) -> anyhow::Result<()> {
+    let body = report.render(webhook)?;
+    let timeout = webhook
+        .timeout
+        .to_std()
+        .context("Webhook timeout must not be negative")?;
+    let mut backoff = INITIAL_RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .post(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .timeout(timeout)
+            .body(body.clone())
+            .send()
+            .await
+            .map_err(|e| anyhow!(e))
+            .and_then(|response| {
+                let status = response.status();
+                if !status.is_success() {
+                    bail!("Webhook responded with status {status}");
+                }
+                Ok(())
+            });
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= webhook.retries => {
+                return Err(e.context(format!(
+                    "Delivering to {} failed after {} attempts",
+                    webhook.url,
+                    attempt + 1
+                )))
+            }
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(MAX_RETRY_BACKOFF);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Received {
+        failures_left: AtomicUsize,
+        bodies: Mutex<Vec<String>>,
+    }
+
+    async fn receive(State(received): State<Arc<Received>>, body: String) -> StatusCode {
+        let fail = received
+            .failures_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if fail {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        received.bodies.lock().unwrap().push(body);
+        StatusCode::NO_CONTENT
+    }
+
+    async fn stand_in(failures: usize) -> (String, Arc<Received>) {
+        let received = Arc::new(Received {
+            failures_left: AtomicUsize::new(failures),
+            ..Default::default()
+        });
+        let router = Router::new()
+            .route("/hook", post(receive))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url, received)
+    }
+
+    fn report(dry_run: bool) -> TickReport {
+        TickReport::new(
+            "seedbox \"a\"",
+            Utc::now(),
+            dry_run,
+            vec![ReportedAction {
+                policy: "ratio".to_string(),
+                hash: "abcd".to_string(),
+                name: "testcase".to_string(),
+                action: Action::RemoveWithData,
+                total_size: 1000,
+                reason: ConditionMatch::Ratio(2.0),
+                success: !dry_run,
+            }],
+        )
+    }
+
+    #[tokio::test]
+    async fn delivers_after_retrying() {
+        let (url, received) = stand_in(1).await;
+        let webhook = Webhook::new(&url).with_retries(1).unwrap();
+        let report = report(false);
+        deliver(&reqwest::Client::new(), &webhook, &report)
+            .await
+            .unwrap();
+        let bodies = received.bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let delivered: TickReport = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(delivered, report);
+    }
+
+    #[tokio::test]
+    async fn gives_up_eventually() {
+        let (url, received) = stand_in(5).await;
+        let webhook = Webhook::new(&url).with_retries(1).unwrap();
+        assert!(deliver(&reqwest::Client::new(), &webhook, &report(false))
+            .await
+            .is_err());
+        assert!(received.bodies.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn renders_templates() {
+        let webhook = Webhook::new("http://localhost").with_template(r#"{"text": "{{summary}}"}"#);
+        let body: serde_json::Value =
+            serde_json::from_str(&report(false).render(&webhook).unwrap()).unwrap();
+        assert_eq!(
+            body["text"],
+            "Removed 1 torrents (1000 bytes) on seedbox \"a\""
+        );
+    }
+
+    #[test]
+    fn dry_runs_only_when_asked() {
+        let webhook = Webhook::new("http://localhost");
+        assert!(report(false).is_wanted_by(&webhook));
+        assert!(!report(true).is_wanted_by(&webhook));
+        assert!(report(true).is_wanted_by(&webhook.with_include_dry_run(true)));
+        assert!(!TickReport::new("x", Utc::now(), false, vec![])
+            .is_wanted_by(&Webhook::new("http://localhost")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drains_deliveries() {
+        let deliveries = Deliveries::default();
+        let delivered = Arc::new(AtomicUsize::new(0));
+        for delay in [1, 5] {
+            let delivered = delivered.clone();
+            deliveries.spawn(async move {
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                delivered.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        deliveries.drain().await;
+        assert_eq!(delivered.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,545 @@
+//! A journal of what gearbox-maintenance saw and did on an instance,
+//! kept across restarts.
+//!
+//! Each instance gets its own JSON-lines file in the state
+//! directory. Records only get appended; when the store is opened,
+//! and every [`COMPACTION_INTERVAL_HOURS`] after that, the journal
+//! gets rewritten without records that are older than
+//! [`RETENTION_DAYS`]. Torrents that are still on the instance get a
+//! sample at least every [`SAMPLE_INTERVAL_HOURS`]; those that left
+//! it stop getting records, so they are forgotten once their last
+//! record expires.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::policy::{Action, ConditionMatch, ConditionMatchKind};
+use crate::Torrent;
+
+/// How long records are kept around after they were written.
+pub const RETENTION_DAYS: i64 = 30;
+
+/// How often a running store drops expired records.
+pub const COMPACTION_INTERVAL_HOURS: i64 = 24;
+
+/// How often a torrent whose upload statistics didn't change gets a
+/// sample anyway, so that it doesn't expire while it's still there.
+pub const SAMPLE_INTERVAL_HOURS: i64 = 24;
+
+/// A snapshot of a torrent's upload statistics.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Sample {
+    pub at: DateTime<Utc>,
+    pub upload_ratio: f64,
+    pub uploaded_ever: i64,
+}
+
+/// A policy's verdict on a torrent.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Decision {
+    pub at: DateTime<Utc>,
+    pub policy: String,
+    pub decision: ConditionMatch,
+}
+
+/// An action that was executed on a torrent.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Executed {
+    pub at: DateTime<Utc>,
+    pub policy: String,
+    pub action: Action,
+    pub success: bool,
+}
+
+/// Everything the store knows about one torrent.
+#[derive(PartialEq, Clone, Debug)]
+pub struct TorrentHistory {
+    pub name: String,
+    pub first_seen: DateTime<Utc>,
+    pub samples: Vec<Sample>,
+    pub decisions: Vec<Decision>,
+    pub actions: Vec<Executed>,
+}
+
+impl TorrentHistory {
+    /// The time of the most recent record about this torrent.
+    fn last_activity(&self) -> DateTime<Utc> {
+        [
+            Some(self.first_seen),
+            self.samples.last().map(|s| s.at),
+            self.decisions.last().map(|d| d.at),
+            self.actions.last().map(|a| a.at),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(self.first_seen)
+    }
+
+    /// The last decision that `policy` made on this torrent.
+    pub fn last_decision(&self, policy: &str) -> Option<&Decision> {
+        self.decisions.iter().rev().find(|d| d.policy == policy)
+    }
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record {
+    Seen {
+        hash: String,
+        name: String,
+        at: DateTime<Utc>,
+    },
+    Sample {
+        hash: String,
+        #[serde(flatten)]
+        sample: Sample,
+    },
+    Decision {
+        hash: String,
+        #[serde(flatten)]
+        decision: Decision,
+    },
+    Action {
+        hash: String,
+        #[serde(flatten)]
+        executed: Executed,
+    },
+}
+
+/// Persistent history of the torrents on one transmission instance.
+#[derive(Debug, Default)]
+pub struct StateStore {
+    path: Option<PathBuf>,
+    journal: Option<BufWriter<File>>,
+    torrents: HashMap<String, TorrentHistory>,
+    compacted_at: Option<DateTime<Utc>>,
+}
+
+impl StateStore {
+    /// Returns a store that forgets everything when the process exits.
+    pub fn in_memory() -> Self {
+        Default::default()
+    }
+
+    /// Opens (or creates) the journal for `instance` in `dir`.
+    ///
+    /// Lines that can't be parsed, like one that a crash cut short,
+    /// are skipped, and the next compaction drops them for good.
+    pub fn open(dir: &Path, instance: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = Self::journal_path(dir, instance);
+        let mut store = Self {
+            torrents: Self::replay(&path)?,
+            path: Some(path),
+            ..Default::default()
+        };
+        store.compact(Utc::now())?;
+        Ok(store)
+    }
+
+    /// Returns the journal's path, with the instance name
+    /// percent-encoded so that every name gets a file of its own.
+    fn journal_path(dir: &Path, instance: &str) -> PathBuf {
+        let mut name = String::new();
+        for byte in instance.bytes() {
+            if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+                name.push(byte as char);
+            } else {
+                name.push_str(&format!("%{byte:02X}"));
+            }
+        }
+        dir.join(format!("{name}.jsonl"))
+    }
+
+    fn replay(path: &Path) -> io::Result<HashMap<String, TorrentHistory>> {
+        let mut torrents = HashMap::new();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(torrents),
+            Err(e) => return Err(e),
+        };
+        let mut skipped = 0;
+        for (number, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line?;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match serde_json::from_slice::<Record>(&line) {
+                Ok(record) => Self::apply(&mut torrents, record),
+                Err(e) => {
+                    skipped += 1;
+                    warn!(path=?path, line=number + 1, error=%e, "Skipping unreadable state journal record");
+                }
+            }
+        }
+        if skipped > 0 {
+            warn!(path=?path, skipped, "State journal had unreadable records, keeping the rest");
+        }
+        Ok(torrents)
+    }
+
+    fn apply(torrents: &mut HashMap<String, TorrentHistory>, record: Record) {
+        match record {
+            Record::Seen { hash, name, at } => {
+                torrents.entry(hash).or_insert_with(|| TorrentHistory {
+                    name,
+                    first_seen: at,
+                    samples: vec![],
+                    decisions: vec![],
+                    actions: vec![],
+                });
+            }
+            Record::Sample { hash, sample } => {
+                if let Some(history) = torrents.get_mut(&hash) {
+                    history.samples.push(sample);
+                }
+            }
+            Record::Decision { hash, decision } => {
+                if let Some(history) = torrents.get_mut(&hash) {
+                    history.decisions.push(decision);
+                }
+            }
+            Record::Action { hash, executed } => {
+                if let Some(history) = torrents.get_mut(&hash) {
+                    history.actions.push(executed);
+                }
+            }
+        }
+    }
+
+    /// Drops the records that are older than the retention period,
+    /// and rewrites the journal with the remaining ones.
+    fn compact(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.compacted_at = Some(now);
+        let cutoff = now - Duration::days(RETENTION_DAYS);
+        self.torrents.retain(|_, h| h.last_activity() >= cutoff);
+        for history in self.torrents.values_mut() {
+            history.samples.retain(|s| s.at >= cutoff);
+            history.decisions.retain(|d| d.at >= cutoff);
+            history.actions.retain(|a| a.at >= cutoff);
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for (hash, history) in self.torrents.iter() {
+            for record in Self::records_for(hash, history) {
+                serde_json::to_writer(&mut writer, &record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        writer.flush()?;
+        fs::rename(&tmp, path)?;
+        self.journal = Some(BufWriter::new(OpenOptions::new().append(true).open(path)?));
+        Ok(())
+    }
+
+    /// Compacts the store if the last compaction was more than
+    /// [`COMPACTION_INTERVAL_HOURS`] ago, so that neither the journal
+    /// nor the store's memory grow without bounds.
+    pub fn compact_if_due(&mut self, now: DateTime<Utc>) {
+        if self
+            .compacted_at
+            .is_some_and(|at| now - at < Duration::hours(COMPACTION_INTERVAL_HOURS))
+        {
+            return;
+        }
+        self.flush();
+        if let Err(e) = self.compact(now) {
+            warn!(error=%e, "Could not compact state journal");
+        }
+    }
+
+    fn records_for(hash: &str, history: &TorrentHistory) -> Vec<Record> {
+        let hash = hash.to_string();
+        let mut records = vec![Record::Seen {
+            hash: hash.clone(),
+            name: history.name.clone(),
+            at: history.first_seen,
+        }];
+        records.extend(history.samples.iter().map(|sample| Record::Sample {
+            hash: hash.clone(),
+            sample: sample.clone(),
+        }));
+        records.extend(history.decisions.iter().map(|decision| Record::Decision {
+            hash: hash.clone(),
+            decision: decision.clone(),
+        }));
+        records.extend(history.actions.iter().map(|executed| Record::Action {
+            hash: hash.clone(),
+            executed: executed.clone(),
+        }));
+        records
+    }
+
+    fn write(&mut self, record: Record) {
+        if let Some(journal) = self.journal.as_mut() {
+            let result = serde_json::to_writer(&mut *journal, &record)
+                .map_err(io::Error::from)
+                .and_then(|_| journal.write_all(b"\n"));
+            if let Err(e) = result {
+                warn!(error=%e, "Could not write to state journal");
+            }
+        }
+        Self::apply(&mut self.torrents, record);
+    }
+
+    /// Returns what the store knows about the torrent with `hash`.
+    pub fn history(&self, hash: &str) -> Option<&TorrentHistory> {
+        self.torrents.get(hash)
+    }
+
+    /// Notes that `torrent` was seen at `now`, recording its upload
+    /// statistics if they changed since the last sample, or if that
+    /// is more than [`SAMPLE_INTERVAL_HOURS`] old.
+    pub fn observe(&mut self, now: DateTime<Utc>, torrent: &Torrent) {
+        if !self.torrents.contains_key(&torrent.hash) {
+            self.write(Record::Seen {
+                hash: torrent.hash.clone(),
+                name: torrent.name.clone(),
+                at: now,
+            });
+        }
+        let sample = Sample {
+            at: now,
+            upload_ratio: torrent.upload_ratio as f64,
+            uploaded_ever: torrent.uploaded_ever,
+        };
+        let changed = self.torrents[&torrent.hash]
+            .samples
+            .last()
+            .map(|last| {
+                (last.upload_ratio, last.uploaded_ever)
+                    != (sample.upload_ratio, sample.uploaded_ever)
+                    || now - last.at >= Duration::hours(SAMPLE_INTERVAL_HOURS)
+            })
+            .unwrap_or(true);
+        if changed {
+            self.write(Record::Sample {
+                hash: torrent.hash.clone(),
+                sample,
+            });
+        }
+    }
+
+    /// Records the decision that `policy` made on `hash`, if it
+    /// differs from the previous one.
+    pub fn record_decision(
+        &mut self,
+        now: DateTime<Utc>,
+        hash: &str,
+        policy: &str,
+        decision: ConditionMatch,
+    ) {
+        let unchanged = self
+            .history(hash)
+            .and_then(|h| h.last_decision(policy))
+            .map(|last| {
+                ConditionMatchKind::from(last.decision) == ConditionMatchKind::from(decision)
+            })
+            .unwrap_or(false);
+        if !unchanged {
+            self.write(Record::Decision {
+                hash: hash.to_string(),
+                decision: Decision {
+                    at: now,
+                    policy: policy.to_string(),
+                    decision,
+                },
+            });
+        }
+    }
+
+    /// Records that `action` was executed on `hash`.
+    pub fn record_action(
+        &mut self,
+        now: DateTime<Utc>,
+        hash: &str,
+        policy: &str,
+        action: Action,
+        success: bool,
+    ) {
+        self.write(Record::Action {
+            hash: hash.to_string(),
+            executed: Executed {
+                at: now,
+                policy: policy.to_string(),
+                action,
+                success,
+            },
+        });
+    }
+
+    /// Writes any buffered records to disk.
+    pub fn flush(&mut self) {
+        if let Some(Err(e)) = self.journal.as_mut().map(Write::flush) {
+            warn!(error=%e, "Could not flush state journal");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use transmission_rpc::types::{ErrorType, TorrentStatus};
+
+    fn torrent(upload_ratio: f32) -> Torrent {
+        Torrent {
+            id: 1,
+            hash: "abcd".to_string(),
+            name: "testcase".to_string(),
+            done_date: Some(Utc::now()),
+            error: ErrorType::Ok,
+            error_string: "".to_string(),
+            upload_ratio,
+            computed_upload_ratio: upload_ratio as f64,
+            uploaded_ever: (upload_ratio * 100.0) as i64,
+            status: TorrentStatus::Seeding,
+            num_files: 1,
+            total_size: 100,
+            trackers: vec![],
+        }
+    }
+
+    #[test]
+    fn survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let first_seen = Utc::now() - Duration::hours(3);
+        {
+            let mut store = StateStore::open(dir.path(), "http://localhost:9091").unwrap();
+            store.observe(first_seen, &torrent(0.5));
+            store.observe(first_seen + Duration::hours(1), &torrent(0.5));
+            store.observe(first_seen + Duration::hours(2), &torrent(1.5));
+            store.record_decision(first_seen, "abcd", "pol", ConditionMatch::None);
+            store.record_decision(first_seen, "abcd", "pol", ConditionMatch::None);
+            store.record_decision(first_seen, "abcd", "pol", ConditionMatch::Ratio(1.5));
+            store.record_action(first_seen, "abcd", "pol", Action::RemoveWithData, true);
+            store.flush();
+        }
+        let store = StateStore::open(dir.path(), "http://localhost:9091").unwrap();
+        let history = store.history("abcd").unwrap();
+        assert_eq!(history.first_seen, first_seen);
+        assert_eq!(history.samples.len(), 2);
+        assert_eq!(history.decisions.len(), 2);
+        assert_eq!(
+            history.last_decision("pol").unwrap().decision,
+            ConditionMatch::Ratio(1.5)
+        );
+        assert_eq!(history.actions.len(), 1);
+        assert!(history.actions[0].success);
+    }
+
+    #[test]
+    fn drops_expired_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let long_ago = Utc::now() - Duration::days(RETENTION_DAYS + 1);
+        {
+            let mut store = StateStore::open(dir.path(), "inst").unwrap();
+            store.observe(long_ago, &torrent(0.5));
+            store.flush();
+        }
+        let store = StateStore::open(dir.path(), "inst").unwrap();
+        assert_eq!(store.history("abcd"), None);
+    }
+
+    #[test]
+    fn keeps_records_before_a_torn_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        {
+            let mut store = StateStore::open(dir.path(), "inst").unwrap();
+            store.observe(now, &torrent(0.5));
+            store.flush();
+        }
+        let path = StateStore::journal_path(dir.path(), "inst");
+        let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.write_all(b"{\"event\": \"seen\", \"hash").unwrap();
+        drop(journal);
+
+        let mut store = StateStore::open(dir.path(), "inst").unwrap();
+        assert_eq!(store.history("abcd").unwrap().samples.len(), 1);
+        store.observe(now + Duration::hours(1), &torrent(1.5));
+        store.flush();
+
+        // The torn line is gone, so the journal stays readable:
+        let store = StateStore::open(dir.path(), "inst").unwrap();
+        assert_eq!(store.history("abcd").unwrap().samples.len(), 2);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .all(|line| serde_json::from_str::<Record>(line).is_ok()));
+    }
+
+    #[test]
+    fn compacts_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc::now();
+        let mut store = StateStore::open(dir.path(), "inst").unwrap();
+        store.observe(start, &torrent(0.5));
+
+        store.compact_if_due(start + Duration::hours(1));
+        assert!(store.history("abcd").is_some());
+
+        // Once the torrent is gone from the instance, its records
+        // expire:
+        let later = start + Duration::days(RETENTION_DAYS) + Duration::hours(1);
+        store.compact_if_due(later);
+        assert_eq!(store.history("abcd"), None);
+        assert_eq!(
+            fs::read_to_string(StateStore::journal_path(dir.path(), "inst")).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn remembers_torrents_that_stay() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc::now();
+        let mut store = StateStore::open(dir.path(), "inst").unwrap();
+        for hours in (0..(RETENTION_DAYS + 5) * 24).step_by(5) {
+            let now = start + Duration::hours(hours);
+            store.observe(now, &torrent(0.5));
+            store.compact_if_due(now);
+        }
+        store.flush();
+        assert_eq!(store.history("abcd").unwrap().first_seen, start);
+
+        let store = StateStore::open(dir.path(), "inst").unwrap();
+        assert_eq!(store.history("abcd").unwrap().first_seen, start);
+    }
+
+    #[test]
+    fn instances_get_separate_journals() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = StateStore::open(dir.path(), "a-b").unwrap();
+            store.observe(Utc::now(), &torrent(0.5));
+            store.flush();
+        }
+        for other in ["a_b", "a.b", "a%2Db"] {
+            let store = StateStore::open(dir.path(), other).unwrap();
+            assert_eq!(store.history("abcd"), None, "{other}");
+        }
+        let store = StateStore::open(dir.path(), "a-b").unwrap();
+        assert!(store.history("abcd").is_some());
+    }
+
+    #[test]
+    fn in_memory_store_forgets_expired_torrents() {
+        let start = Utc::now();
+        let mut store = StateStore::in_memory();
+        store.observe(start, &torrent(0.5));
+        store.compact_if_due(start);
+        store.compact_if_due(start + Duration::days(RETENTION_DAYS + 1));
+        assert_eq!(store.history("abcd"), None);
+    }
+}
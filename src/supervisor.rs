@@ -0,0 +1,158 @@
+//! Restarts tasks that panic, so one misbehaving instance can't take
+//! the others down with it.
+
+use std::{any::Any, future::Future, time::Duration};
+
+use tokio::{sync::watch, task::JoinHandle, time};
+use tracing::{error, info};
+
+use crate::metrics::Metrics;
+
+/// How long to wait before restarting a task that panicked; each
+/// further panic doubles the wait.
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest wait between restarts. A task that ran at least this
+/// long before panicking gets restarted after the initial backoff again.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(no message)")
+}
+
+/// Aborts a task once its handle goes away, so that aborting the
+/// supervisor also stops the task it supervises.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs the tasks that `run` creates for the instance named `name`
+/// until one finishes normally or `shutdown` fires, restarting them
+/// with exponential backoff whenever they panic.
+pub(crate) async fn supervise<F, Fut>(
+    name: &str,
+    metrics: &Metrics,
+    mut shutdown: watch::Receiver<()>,
+    mut run: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = INITIAL_RESTART_BACKOFF;
+    loop {
+        let started = time::Instant::now();
+        let mut task = AbortOnDrop(tokio::spawn(run()));
+        let panic = match (&mut task.0).await {
+            Ok(()) => return,
+            Err(e) => match e.try_into_panic() {
+                Ok(panic) => panic,
+                // Only happens when the runtime shuts down:
+                Err(_) => return,
+            },
+        };
+        metrics.track_panic(name);
+        if started.elapsed() >= MAX_RESTART_BACKOFF {
+            backoff = INITIAL_RESTART_BACKOFF;
+        }
+        error!(
+            instance = name,
+            panic = panic_message(&*panic),
+            restart_in = ?backoff,
+            "Instance task panicked, restarting it",
+        );
+        tokio::select! {
+            biased;
+            _ = shutdown.changed() => return,
+            _ = time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+        info!(instance = name, "Restarting instance task");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use prometheus_client::{encoding::text::encode, registry::Registry};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_panicking_tasks() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let (_shutdown_tx, shutdown) = watch::channel(());
+        let runs = Arc::new(AtomicUsize::new(0));
+        supervise("seedbox", &metrics, shutdown, || {
+            let runs = runs.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("oh no");
+                }
+            }
+        })
+        .await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        let mut output = String::new();
+        encode(&mut output, &registry).unwrap();
+        assert!(output.contains(r#"instance_task_panic_count_total{transmission_url="seedbox"} 2"#));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborting_stops_the_supervised_task() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let (_shutdown_tx, shutdown) = watch::channel(());
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let supervisor = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                supervise("seedbox", &metrics, shutdown, || {
+                    let ticks = ticks.clone();
+                    async move {
+                        loop {
+                            ticks.fetch_add(1, Ordering::SeqCst);
+                            time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                })
+                .await
+            }
+        });
+        time::sleep(Duration::from_millis(2500)).await;
+        supervisor.abort();
+        let _ = supervisor.await;
+        let before = ticks.load(Ordering::SeqCst);
+        time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), before);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_restarting_on_shutdown() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::for_registry(&mut registry, &Default::default());
+        let (shutdown_tx, shutdown) = watch::channel(());
+        shutdown_tx.send_replace(());
+        let runs = Arc::new(AtomicUsize::new(0));
+        supervise("seedbox", &metrics, shutdown, || {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                panic!("oh no");
+            }
+        })
+        .await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
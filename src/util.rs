@@ -1,4 +1,5 @@
-/// Parses and serializes an optional chrono duration
+/// Parses and serializes an optional chrono duration. Fields using
+/// this should also skip serializing `None`.
 pub mod chrono_optional_duration {
     use chrono::Duration;
     use serde::{self, Deserialize, Deserializer, Serializer};
@@ -7,22 +8,18 @@ pub mod chrono_optional_duration {
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        Ok(Some(
-            Duration::from_std(parse_duration::parse(&s).map_err(serde::de::Error::custom)?)
-                .map_err(serde::de::Error::custom)?,
-        ))
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| super::parse_duration(&s).map_err(serde::de::Error::custom))
+            .transpose()
     }
 
     pub fn serialize<S>(dur: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        if let Some(dur) = dur {
-            let s = format!("{dur}");
-            serializer.serialize_str(&s)
-        } else {
-            serializer.serialize_str("")
+        match dur {
+            Some(dur) => serializer.serialize_str(&super::format_duration(*dur)),
+            None => serializer.serialize_none(),
         }
     }
 }
@@ -37,15 +34,148 @@ pub mod chrono_duration {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Duration::from_std(parse_duration::parse(&s).map_err(serde::de::Error::custom)?)
-            .map_err(serde::de::Error::custom)
+        super::parse_duration(&s).map_err(serde::de::Error::custom)
     }
 
     pub fn serialize<S>(dur: &Duration, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let s = format!("{dur}");
-        serializer.serialize_str(&s)
+        serializer.serialize_str(&super::format_duration(*dur))
+    }
+}
+
+/// Serializes a chrono duration as a whole number of seconds.
+pub mod chrono_duration_seconds {
+    use chrono::Duration;
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Duration::seconds(i64::deserialize(deserializer)?))
+    }
+
+    pub fn serialize<S>(dur: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i64(dur.num_seconds())
+    }
+}
+
+/// Parses a human-readable duration like `"3 days"` or `"1h 30min"`.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let duration =
+        parse_duration::parse(s).map_err(|e| format!("Could not parse duration {s:?}: {e}"))?;
+    chrono::Duration::from_std(duration).map_err(|e| format!("Duration {s:?} is out of range: {e}"))
+}
+
+/// Writes a duration in the largest units that represent it exactly,
+/// like `3d 4h`, in a way that [`parse_duration`] reads back.
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let mut formatted = String::new();
+    if duration < chrono::Duration::zero() {
+        formatted.push('-');
+    }
+    // Whole seconds and the nanoseconds after them are counted
+    // separately, so that no duration within chrono's range
+    // can overflow or get truncated:
+    let seconds = duration.num_seconds().unsigned_abs();
+    let nanos = u64::from(duration.subsec_nanos().unsigned_abs());
+    if seconds == 0 && nanos == 0 {
+        return "0s".to_string();
+    }
+    let mut separator = "";
+    for (mut rest, units) in [
+        (
+            seconds,
+            &[(86_400, "d"), (3_600, "h"), (60, "min"), (1, "s")][..],
+        ),
+        (nanos, &[(1_000_000, "ms"), (1_000, "us"), (1, "ns")][..]),
+    ] {
+        for &(unit, name) in units {
+            let count = rest / unit;
+            if count > 0 {
+                formatted.push_str(&format!("{separator}{count}{name}"));
+                separator = " ";
+                rest %= unit;
+            }
+        }
+    }
+    formatted
+}
+
+/// Parses a human-readable byte size like `"500 GiB"`, `"2TB"` or
+/// `"1024"` into a number of bytes.
+///
+/// Decimal (`kB`, `MB`, ...) and binary (`KiB`, `MiB`, ...) units are
+/// both understood; unit names are case-insensitive.
+pub fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|e| format!("Could not parse byte size {s:?}: {e}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "p" | "pb" => 1_000_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        "pib" => 1 << 50,
+        other => return Err(format!("Unknown byte size unit {other:?} in {s:?}")),
+    };
+    let bytes = number * multiplier as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(format!("Byte size {s:?} is out of range"));
+    }
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_duration, parse_byte_size, parse_duration};
+    use chrono::Duration;
+    use test_case::test_case;
+
+    #[test_case("1024", Some(1024); "bare number")]
+    #[test_case("2 TiB", Some(2 << 40); "binary unit")]
+    #[test_case("500GB", Some(500_000_000_000); "decimal unit without space")]
+    #[test_case("1.5 kib", Some(1536); "fractional, lowercase")]
+    #[test_case("12 horses", None; "unknown unit")]
+    #[test_case("GiB", None; "no number")]
+    #[test_case("16 EiB", None; "exabytes are not a unit")]
+    #[test_case("16384 PiB", None; "just too large")]
+    #[test_case("16383 PiB", Some(16383 << 50); "largest")]
+    #[test_case(&"9".repeat(400), None; "infinite")]
+    fn byte_sizes(input: &str, expected: Option<u64>) {
+        assert_eq!(parse_byte_size(input).ok(), expected);
+    }
+
+    #[test_case(Duration::milliseconds(-1500), "-1s 500ms"; "negative")]
+    #[test_case(Duration::nanoseconds(1_000_001), "1ms 1ns"; "sub-second units")]
+    #[test_case(Duration::days(30_000), "30000d"; "more days than fit an i32 of seconds")]
+    fn formats_durations(duration: Duration, expected: &str) {
+        assert_eq!(format_duration(duration), expected);
+    }
+
+    #[test_case(Duration::MAX; "maximum")]
+    #[test_case(Duration::MIN; "minimum")]
+    #[test_case(Duration::days(50_000) + Duration::nanoseconds(7); "long, with nanoseconds")]
+    fn formats_extreme_durations(duration: Duration) {
+        let formatted = format_duration(duration);
+        if duration > Duration::zero() {
+            assert_eq!(parse_duration(&formatted), Ok(duration), "{formatted}");
+        }
     }
 }
@@ -0,0 +1,124 @@
+use gearbox_maintenance::config::{
+    configure,
+    format::{ConfigFormat, DeclarativeConfig},
+    Instance,
+};
+
+/// A config that uses every builder, so that exporting it and loading
+/// the export back has to preserve every setting.
+fn every_builder(dir: &std::path::Path) -> String {
+    let dir = dir.display();
+    format!(
+        r#"
+      [
+        defaults()
+          .user("default-user")
+          .password_file("{dir}/password")
+          .poll_interval(duration("7min 30s"))
+          .max_deletions_per_tick(9)
+          .max_bytes_per_hour(bytes("3 GiB"))
+          .action_window("01:00-05:00", "Asia/Tokyo")
+          .dry_run(true)
+          .trackers(["shared.example"])
+          .max_ratio(3.5)
+          .min_seeding_time("36h")
+          .max_seeding_time("90 days"),
+        notify_webhook("http://hooks.example/everywhere")
+          .include_dry_run(true)
+          .template(`{{"text": "{{{{summary}}}}"}}`)
+          .timeout("2500ms")
+          .retries(7),
+        rules(
+          transmission("https://seedbox.example/transmission/rpc")
+            .name("everything")
+            .user("admin")
+            .password("hunter2")
+            .poll_interval("1h 2min 3s")
+            .max_deletions_per_tick(20)
+            .max_bytes_per_hour("2 TiB")
+            .max_deletion_fraction(0.25)
+            .min_torrent_count(50)
+            .notify_webhook("http://hooks.example/plain")
+            .notify_webhook(notify_webhook("http://hooks.example/fancy").timeout(duration("1s")))
+            .warn_unmanaged_bytes(bytes("750 GB"))
+            .ca_bundle("{dir}/ca.pem")
+            .client_certificate("{dir}/client.pem", "{dir}/client.key")
+            .insecure_skip_verify(true)
+            .rpc_timeout("45s")
+            .rpc_retries(4)
+            .max_poll_backoff("6h")
+            .action_window("22:00-04:00", "America/New_York")
+            .dry_run(false),
+          [
+            delete_policy("real",
+                          on_trackers(["one.example", "two.example"])
+                            .min_file_count(2)
+                            .max_file_count(40),
+                          matching()
+                            .max_ratio(2.3)
+                            .min_seeding_time(duration("2 days"))
+                            .max_seeding_time("14 days 12h"))
+              .max_deletions_per_tick(3)
+              .max_bytes_per_hour("100 MiB")
+              .action_window("03:00-04:00", "UTC")
+              .dry_run(true),
+            noop_delete_policy("noop", on_trackers(["three.example"]), matching()),
+          ]
+        ),
+        rules(
+          transmission("http://inheriting.example/transmission/rpc").password_env("PATH"),
+          [delete_policy("inherits", on_trackers([]), matching().max_ratio(1.0))]
+        ),
+      ]
+    "#
+    )
+}
+
+#[test]
+fn round_trips_every_builder_option() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("password"), "from a file\n")?;
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    std::fs::write(dir.path().join("ca.pem"), certified.cert.pem())?;
+    std::fs::write(dir.path().join("client.pem"), certified.cert.pem())?;
+    std::fs::write(
+        dir.path().join("client.key"),
+        certified.key_pair.serialize_pem(),
+    )?;
+    let rhai = dir.path().join("config.rhai");
+    std::fs::write(&rhai, every_builder(dir.path()))?;
+    let instances = configure(&rhai).map_err(|e| anyhow::anyhow!("{e}"))?;
+    assert!(!instances[1].inherited.is_empty());
+
+    for (format, extension) in [
+        (ConfigFormat::Toml, "toml"),
+        (ConfigFormat::Yaml, "yaml"),
+        (ConfigFormat::Json, "json"),
+    ] {
+        let exported = DeclarativeConfig::from_instances(instances.clone())
+            .render(format)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let path = dir.path().join(format!("exported.{extension}"));
+        std::fs::write(&path, &exported)?;
+        let loaded = configure(&path).map_err(|e| anyhow::anyhow!("{e}\n{exported}"))?;
+        // Exports spell out what came from defaults, so nothing is
+        // inherited when loading them:
+        let resolved: Vec<_> = instances
+            .iter()
+            .cloned()
+            .map(|instance| Instance {
+                inherited: Default::default(),
+                ..instance
+            })
+            .collect();
+        assert_eq!(loaded, resolved, "{format} export:\n{exported}");
+    }
+    Ok(())
+}
+
+#[test]
+fn cannot_export_to_rhai() {
+    assert!(DeclarativeConfig::default()
+        .render(ConfigFormat::Rhai)
+        .is_err());
+}
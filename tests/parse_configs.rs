@@ -1,5 +1,9 @@
 use anyhow::bail;
-use gearbox_maintenance::config::configure;
+use gearbox_maintenance::config::{
+    configure, configure_as,
+    format::ConfigFormat,
+    lint::{lint, Severity},
+};
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::PathBuf;
@@ -69,3 +73,841 @@ fn noop_and_non_noop_policies() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn deletion_limits() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [rules(
+         transmission("x").max_deletions_per_tick(20).max_bytes_per_hour("2 TiB"),
+         [
+           delete_policy("limited", on_trackers(["foo"]), matching().max_ratio(1.0))
+             .max_deletions_per_tick(5),
+           delete_policy("unlimited", on_trackers(["bar"]), matching().max_ratio(1.0)),
+         ]
+       )
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        assert_eq!(inst.transmission.limits.max_deletions_per_tick, Some(20));
+        assert_eq!(inst.transmission.limits.max_bytes_per_hour, Some(2 << 40));
+        assert_eq!(inst.policies[0].limits.max_deletions_per_tick, Some(5));
+        assert!(inst.policies[1].limits.is_unlimited());
+    } else {
+        bail!("No instances")
+    }
+    Ok(())
+}
+
+#[test]
+fn unmanaged_warning() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [rules(transmission("x").warn_unmanaged_bytes("500 GB"), [])]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        assert_eq!(
+            inst.transmission.warn_unmanaged_bytes,
+            Some(500_000_000_000)
+        );
+    } else {
+        bail!("No instances")
+    }
+    Ok(())
+}
+
+#[test]
+fn circuit_breaker() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [rules(
+         transmission("x").max_deletion_fraction(0.3).min_torrent_count(10),
+         []
+       )
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        assert_eq!(
+            inst.transmission.circuit_breaker.max_deletion_fraction,
+            Some(0.3)
+        );
+        assert_eq!(
+            inst.transmission.circuit_breaker.min_torrent_count,
+            Some(10)
+        );
+    } else {
+        bail!("No instances")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"[rules(transmission("x").max_deletion_fraction(30.0), [])]"#.to_string(),
+        HashMap::from([]),
+    )?;
+    assert!(configure(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn webhooks() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        notify_webhook("http://chat.example/hook")
+          .include_dry_run(true)
+          .template(`{"text": "{{summary}}"}`),
+        rules(transmission("a").notify_webhook("http://a.example/hook"), []),
+        rules(transmission("b"), []),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [a, b] = &instances[..] {
+        let urls = |i: &gearbox_maintenance::config::Instance| -> Vec<String> {
+            i.transmission
+                .webhooks
+                .iter()
+                .map(|w| w.url.clone())
+                .collect()
+        };
+        assert_eq!(
+            urls(a),
+            vec!["http://a.example/hook", "http://chat.example/hook"]
+        );
+        assert_eq!(urls(b), vec!["http://chat.example/hook"]);
+        assert!(b.transmission.webhooks[0].include_dry_run);
+        assert!(b.transmission.webhooks[0].template.is_some());
+    } else {
+        bail!("Expected two instances, got {instances:?}")
+    }
+
+    for (config, message) in [
+        (
+            r#"[notify_webhook("http://chat.example/hook").retries(100)]"#,
+            "retries must be between 0 and 10",
+        ),
+        (
+            r#"[notify_webhook("http://chat.example/hook").timeout("0s")]"#,
+            "timeout must be longer than zero",
+        ),
+        (
+            r#"[notify_webhook("chat.example/hook"), rules(transmission("a"), [])]"#,
+            "invalid URL",
+        ),
+        (
+            r#"[rules(transmission("a").notify_webhook("ftp://chat.example/hook"), [])]"#,
+            "expected an http or https URL",
+        ),
+    ] {
+        let (path, _tmpdir) = build_config(config.to_string(), HashMap::from([]))?;
+        let error = configure(&path).unwrap_err().to_string();
+        assert!(error.contains(message), "{error}");
+    }
+
+    let (path, _tmpdir) = build_config(r#"[42]"#.to_string(), HashMap::from([]))?;
+    assert!(configure(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn instance_names() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        rules(transmission("http://proxy/a").name("seedbox-a"), []),
+        rules(transmission("http://proxy/b"), []),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [a, b] = &instances[..] {
+        assert_eq!(a.name(), "seedbox-a");
+        assert_eq!(b.name(), "http://proxy/b");
+    } else {
+        bail!("Expected two instances, got {instances:?}")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        rules(transmission("http://proxy/a").name("seedbox"), []),
+        rules(transmission("http://proxy/b").name("seedbox"), []),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    assert!(configure(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn password_secrets() -> anyhow::Result<()> {
+    std::env::set_var("GEARBOX_MAINTENANCE_TEST_PASSWORD", "from-env");
+    let secrets = tempdir()?;
+    let secret_file = secrets.path().join("secret");
+    std::fs::write(&secret_file, "from-file\n")?;
+    let (path, _tmpdir) = build_config(
+        format!(
+            r#"
+      [
+        rules(transmission("a").user("u").password_env("GEARBOX_MAINTENANCE_TEST_PASSWORD"), []),
+        rules(transmission("b").user("u").password_file({secret_file:?}), []),
+      ]
+    "#
+        ),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [a, b] = &instances[..] {
+        let reveal = |i: &gearbox_maintenance::config::Instance| {
+            i.transmission.password.as_ref().unwrap().reveal().unwrap()
+        };
+        assert_eq!(reveal(a), "from-env");
+        assert_eq!(reveal(b), "from-file");
+        assert!(!format!("{a:?}").contains("from-env"));
+    } else {
+        bail!("Expected two instances, got {instances:?}")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"[rules(transmission("a").password_env("GEARBOX_MAINTENANCE_TEST_UNSET"), [])]"#
+            .to_string(),
+        HashMap::from([]),
+    )?;
+    let error = configure(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("GEARBOX_MAINTENANCE_TEST_UNSET"),
+        "unexpected error: {error}"
+    );
+    Ok(())
+}
+
+#[test]
+fn rpc_settings() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [rules(
+         transmission("x").rpc_timeout("20s").rpc_retries(5).max_poll_backoff("3 hours"),
+         []
+       )]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        let rpc = inst.transmission.rpc;
+        assert_eq!(rpc.timeout, chrono::Duration::seconds(20));
+        assert_eq!(rpc.retries, 5);
+        assert_eq!(rpc.max_poll_backoff, Some(chrono::Duration::hours(3)));
+    } else {
+        bail!("No instances")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"[rules(transmission("x").rpc_retries(-1), [])]"#.to_string(),
+        HashMap::from([]),
+    )?;
+    assert!(configure(&path).is_err());
+
+    let (path, _tmpdir) = write_config(
+        "config.json",
+        r#"{"instances": [{"transmission": {"url": "x", "rpc": {"retries": 4000000000}}, "policies": []}]}"#,
+    )?;
+    let error = configure(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("rpc_retries must be between 0 and 10"),
+        "{error}"
+    );
+    Ok(())
+}
+
+#[test]
+fn action_windows() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [rules(
+         transmission("x").action_window("02:00-06:00", "Europe/Berlin"),
+         [
+           delete_policy("nightly", on_trackers(["foo"]), matching().max_ratio(1.0))
+             .action_window("22:00-04:00", "UTC"),
+         ]
+       )]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        assert_eq!(
+            inst.transmission.action_window.unwrap().to_string(),
+            "02:00-06:00 Europe/Berlin"
+        );
+        assert_eq!(
+            inst.policies[0].action_window.unwrap().to_string(),
+            "22:00-04:00 UTC"
+        );
+    } else {
+        bail!("No instances")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"[rules(transmission("x").action_window("02:00-06:00", "Nowhere/Special"), [])]"#
+            .to_string(),
+        HashMap::from([]),
+    )?;
+    assert!(configure(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn defaults() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        defaults()
+          .user("me")
+          .password("hunter2")
+          .poll_interval("10min")
+          .max_deletions_per_tick(5)
+          .max_ratio(2.0),
+        rules(
+          transmission("inheriting"),
+          [delete_policy("old", on_trackers(["foo"]), matching().max_seeding_time("30 days"))]
+        ),
+        rules(
+          transmission("overriding").user("other").poll_interval("1min"),
+          [delete_policy("ratio", on_trackers(["foo"]), matching().max_ratio(1.0))]
+        ),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inheriting, overriding] = &instances[..] {
+        assert_eq!(inheriting.transmission.user.as_deref(), Some("me"));
+        assert!(inheriting.transmission.password.is_some());
+        assert_eq!(
+            inheriting.transmission.poll_interval(),
+            chrono::Duration::minutes(10)
+        );
+        assert_eq!(inheriting.policies[0].match_when().max_ratio, Some(2.0));
+        assert_eq!(
+            inheriting.inherited.iter().collect::<Vec<_>>(),
+            vec![
+                "policies.old.match.max_ratio",
+                "transmission.limits.max_deletions_per_tick",
+                "transmission.password",
+                "transmission.poll_interval",
+                "transmission.user",
+            ]
+        );
+
+        assert_eq!(overriding.transmission.user.as_deref(), Some("other"));
+        assert!(overriding.transmission.password.is_none());
+        assert_eq!(
+            overriding.transmission.poll_interval(),
+            chrono::Duration::minutes(1)
+        );
+        assert_eq!(overriding.policies[0].match_when().max_ratio, Some(1.0));
+        assert_eq!(
+            overriding.inherited.iter().collect::<Vec<_>>(),
+            vec!["transmission.limits.max_deletions_per_tick"]
+        );
+    } else {
+        bail!("Expected two instances, got {instances:?}")
+    }
+    Ok(())
+}
+
+#[test]
+fn default_trackers() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        defaults().trackers(["shared.example", "other.example"]).max_ratio(2.0),
+        rules(
+          transmission("x"),
+          [
+            delete_policy("inheriting", on_trackers([]), matching()),
+            delete_policy("own", on_trackers(["own.example"]), matching()),
+          ]
+        ),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let [instance] = &instances[..] else {
+        bail!("Expected one instance, got {instances:?}")
+    };
+    let trackers = |index: usize| {
+        let mut trackers: Vec<_> = instance.policies[index]
+            .precondition()
+            .trackers
+            .iter()
+            .cloned()
+            .collect();
+        trackers.sort();
+        trackers
+    };
+    assert_eq!(trackers(0), vec!["other.example", "shared.example"]);
+    assert_eq!(trackers(1), vec!["own.example"]);
+    assert_eq!(
+        instance.inherited.iter().collect::<Vec<_>>(),
+        vec![
+            "policies.inheriting.match.max_ratio",
+            "policies.inheriting.precondition.trackers",
+            "policies.own.match.max_ratio",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn defaults_satisfy_policy_sanity_check() -> anyhow::Result<()> {
+    let policy =
+        r#"rules(transmission("x"), [delete_policy("p", on_trackers(["foo"]), matching())])"#;
+    let (path, _tmpdir) = build_config(format!("[{policy}]"), HashMap::from([]))?;
+    let error = configure(&path).unwrap_err().to_string();
+    assert!(error.contains(r#"Policy "p" on instance "x""#), "{error}");
+
+    let (path, _tmpdir) = build_config(
+        format!(r#"[defaults().max_seeding_time("30 days"), {policy}]"#),
+        HashMap::from([]),
+    )?;
+    configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    let (path, _tmpdir) = build_config(
+        format!("[defaults(), defaults(), {policy}]"),
+        HashMap::from([]),
+    )?;
+    assert!(configure(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn dry_run_overrides() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      [
+        rules(
+          transmission("observing").dry_run(true),
+          [
+            delete_policy("inherits", on_trackers(["foo"]), matching().max_ratio(1.0)),
+            delete_policy("live", on_trackers(["foo"]), matching().max_ratio(1.0)).dry_run(false),
+          ]
+        ),
+        rules(
+          transmission("live"),
+          [
+            delete_policy("inherits", on_trackers(["foo"]), matching().max_ratio(1.0)),
+            delete_policy("new", on_trackers(["foo"]), matching().max_ratio(1.0)).dry_run(true),
+          ]
+        ),
+      ]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [observing, live] = &instances[..] {
+        assert!(observing.is_dry_run(&observing.policies[0]));
+        assert!(!observing.is_dry_run(&observing.policies[1]));
+        assert!(!live.is_dry_run(&live.policies[0]));
+        assert!(live.is_dry_run(&live.policies[1]));
+    } else {
+        bail!("Expected two instances, got {instances:?}")
+    }
+
+    let (path, _tmpdir) = build_config(
+        r#"[defaults().dry_run(true), rules(transmission("x"), [])]"#.to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    assert_eq!(instances[0].transmission.dry_run, Some(true));
+    assert!(instances[0].inherited.contains("transmission.dry_run"));
+    Ok(())
+}
+
+#[test]
+fn durations_and_byte_sizes() -> anyhow::Result<()> {
+    let (path, _tmpdir) = build_config(
+        r#"
+      let week = duration("7 days");
+      let retention = if week > duration("3 days") { week + duration("12h") } else { week };
+      let budget = bytes("2 TiB");
+      [rules(
+         transmission("x")
+           .poll_interval(duration("10min"))
+           .max_bytes_per_hour(budget)
+           .warn_unmanaged_bytes("500 GiB")
+           .rpc_timeout(duration("20s")),
+         [
+           delete_policy("typed", on_trackers(["foo"]),
+                         matching().min_seeding_time("1 day").max_seeding_time(retention))
+             .max_bytes_per_hour(if budget >= bytes("1 TiB") { bytes("1 TiB") } else { budget }),
+         ]
+       )]
+    "#
+        .to_string(),
+        HashMap::from([]),
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let [inst] = &instances[..] {
+        let transmission = &inst.transmission;
+        assert_eq!(transmission.poll_interval(), chrono::Duration::minutes(10));
+        assert_eq!(transmission.limits.max_bytes_per_hour, Some(2 << 40));
+        assert_eq!(transmission.warn_unmanaged_bytes, Some(500 << 30));
+        assert_eq!(transmission.rpc.timeout, chrono::Duration::seconds(20));
+        let policy = &inst.policies[0];
+        assert_eq!(
+            policy.match_when().min_seeding_time,
+            Some(chrono::Duration::days(1))
+        );
+        assert_eq!(
+            policy.match_when().max_seeding_time,
+            Some(chrono::Duration::hours(7 * 24 + 12))
+        );
+        assert_eq!(policy.limits.max_bytes_per_hour, Some(1 << 40));
+    } else {
+        bail!("No instances")
+    }
+    Ok(())
+}
+
+#[test]
+fn unit_errors_have_positions() -> anyhow::Result<()> {
+    for (config, message) in [
+        (
+            r#"[rules(transmission("x").poll_interval(duration("10 blorps")), [])]"#,
+            r#"Could not parse duration "10 blorps""#,
+        ),
+        (
+            r#"[rules(transmission("x").max_bytes_per_hour("12 horses"), [])]"#,
+            r#"Unknown byte size unit "horses""#,
+        ),
+        (
+            r#"[rules(transmission("x").poll_interval(5), [])]"#,
+            "Expected a duration",
+        ),
+        (
+            r#"[rules(transmission("x").poll_interval(duration("90000000000 days") + duration("90000000000 days")), [])]"#,
+            "is out of range",
+        ),
+        (
+            r#"[rules(transmission("x").max_bytes_per_hour(bytes("16000 PiB") + bytes("1000 PiB")), [])]"#,
+            "is out of range",
+        ),
+        (
+            r#"[rules(transmission("x").max_bytes_per_hour("99999 PiB"), [])]"#,
+            r#"Byte size "99999 PiB" is out of range"#,
+        ),
+    ] {
+        let (path, _tmpdir) = build_config(config.to_string(), HashMap::from([]))?;
+        let error = configure(&path).unwrap_err().to_string();
+        assert!(error.contains(message), "{error}");
+        assert!(error.contains("(line 1, position"), "{error}");
+    }
+    Ok(())
+}
+
+fn write_config(name: &str, contents: &str) -> anyhow::Result<(PathBuf, TempDir)> {
+    let tempdir = tempdir()?;
+    let path = tempdir.path().join(name);
+    std::fs::write(&path, contents)?;
+    Ok((path, tempdir))
+}
+
+#[test]
+fn declarative_formats() -> anyhow::Result<()> {
+    let (rhai, _rhai_dir) = write_config(
+        "config.rhai",
+        r#"
+      [
+        defaults().poll_interval("10min").max_ratio(2.0),
+        notify_webhook("http://hooks.example/gearbox"),
+        rules(
+          transmission("http://seedbox:9091/transmission/rpc")
+            .name("seedbox")
+            .max_bytes_per_hour("1 MiB")
+            .action_window("02:00-06:00", "Europe/Berlin"),
+          [delete_policy("old", on_trackers(["tracker.example"]).max_file_count(1),
+                         matching().max_seeding_time("30 days"))]
+        ),
+      ]
+    "#,
+    )?;
+    let (toml, _toml_dir) = write_config(
+        "config.toml",
+        r#"
+[defaults]
+poll_interval = "10min"
+match = { max_ratio = 2.0 }
+
+[[webhooks]]
+url = "http://hooks.example/gearbox"
+
+[[instances]]
+transmission = { url = "http://seedbox:9091/transmission/rpc", name = "seedbox", limits = { max_bytes_per_hour = 1048576 }, action_window = { window = "02:00-06:00", timezone = "Europe/Berlin" } }
+
+[[instances.policies]]
+name = "old"
+precondition = { trackers = ["tracker.example"], max_file_count = 1 }
+match = { max_seeding_time = "30 days" }
+delete_data = true
+"#,
+    )?;
+    let (yaml, _yaml_dir) = write_config(
+        "config.yml",
+        r#"
+defaults:
+  poll_interval: 10min
+  match:
+    max_ratio: 2.0
+webhooks:
+  - url: http://hooks.example/gearbox
+instances:
+  - transmission:
+      url: http://seedbox:9091/transmission/rpc
+      name: seedbox
+      limits:
+        max_bytes_per_hour: 1048576
+      action_window:
+        window: "02:00-06:00"
+        timezone: Europe/Berlin
+    policies:
+      - name: old
+        precondition:
+          trackers: [tracker.example]
+          max_file_count: 1
+        match:
+          max_seeding_time: 30 days
+        delete_data: true
+"#,
+    )?;
+    let (json, _json_dir) = write_config(
+        "config.json",
+        r#"{
+  "defaults": {"poll_interval": "10min", "match": {"max_ratio": 2.0}},
+  "webhooks": [{"url": "http://hooks.example/gearbox"}],
+  "instances": [{
+    "transmission": {
+      "url": "http://seedbox:9091/transmission/rpc",
+      "name": "seedbox",
+      "limits": {"max_bytes_per_hour": 1048576},
+      "action_window": {"window": "02:00-06:00", "timezone": "Europe/Berlin"}
+    },
+    "policies": [{
+      "name": "old",
+      "precondition": {"trackers": ["tracker.example"], "max_file_count": 1},
+      "match": {"max_seeding_time": "30 days"},
+      "delete_data": true
+    }]
+  }]
+}"#,
+    )?;
+    let expected = configure(&rhai).map_err(|e| anyhow::anyhow!("{e}"))?;
+    for path in [toml, yaml, json] {
+        let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+        assert_eq!(instances, expected, "{path:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn declarative_configs_get_checked() -> anyhow::Result<()> {
+    for (contents, message) in [
+        (
+            r#"{"instances": [{"transmission": {"url": "x"}, "policies": [
+                 {"name": "p", "precondition": {"trackers": ["foo"]}, "match": {}, "delete_data": true}
+               ]}]}"#,
+            r#"Policy "p" on instance "x""#,
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x"}, "policies": []},
+                              {"transmission": {"url": "x"}, "policies": []}]}"#,
+            r#"more than one instance named "x""#,
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x", "circuit_breaker": {"max_deletion_fraction": 2.0}}, "policies": []}]}"#,
+            "max_deletion_fraction must be between 0.0 and 1.0",
+        ),
+        (r#"{"instancez": []}"#, "unknown field `instancez`"),
+        (
+            r#"{"webhooks": [{"url": "http://hook", "retries": 4000000000}],
+                "instances": [{"transmission": {"url": "x"}, "policies": []}]}"#,
+            "retries must be between 0 and 10",
+        ),
+        (
+            r#"{"webhooks": [{"url": "http://hook", "timeout": "0s"}],
+                "instances": [{"transmission": {"url": "x"}, "policies": []}]}"#,
+            "timeout must be longer than zero",
+        ),
+        (
+            r#"{"webhooks": [{"url": "hook"}],
+                "instances": [{"transmission": {"url": "x"}, "policies": []}]}"#,
+            "invalid URL",
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x"}, "policies": [], "inherited": ["transmission.dry_run"]}]}"#,
+            "unknown field `inherited`",
+        ),
+    ] {
+        let (path, _tmpdir) = write_config("config.json", contents)?;
+        let error = configure(&path).unwrap_err().to_string();
+        assert!(error.contains(message), "{error}");
+    }
+    Ok(())
+}
+
+#[test]
+fn misspelled_nested_keys_are_rejected() -> anyhow::Result<()> {
+    let policy = |matching: &str| {
+        format!(
+            r#"{{"instances": [{{"transmission": {{"url": "x"}}, "policies": [
+                 {{"name": "p", "precondition": {{"trackers": ["foo"]}}, "match": {matching}, "delete_data": true}}
+               ]}}]}}"#
+        )
+    };
+    for (contents, field) in [
+        (
+            policy(r#"{"max_ratio": 1.0, "min_seeding_tme": "30 days"}"#),
+            "min_seeding_tme",
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x"}, "policies": [
+                 {"name": "p", "precondition": {"trackers": ["foo"], "min_files": 2}, "match": {"max_ratio": 1.0}, "delete_data": true}
+               ]}]}"#
+                .to_string(),
+            "min_files",
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x", "pol_interval": "1 min"}, "policies": []}]}"#
+                .to_string(),
+            "pol_interval",
+        ),
+        (
+            r#"{"instances": [{"transmission": {"url": "x", "limits": {"max_deletions": 1}}, "policies": []}]}"#
+                .to_string(),
+            "max_deletions",
+        ),
+        (
+            r#"{"defaults": {"match": {"max_ration": 1.0}}, "instances": []}"#.to_string(),
+            "max_ration",
+        ),
+        (
+            r#"{"webhooks": [{"url": "http://hook", "retires": 3}], "instances": []}"#.to_string(),
+            "retires",
+        ),
+    ] {
+        let (path, _tmpdir) = write_config("config.json", &contents)?;
+        let error = configure(&path).unwrap_err().to_string();
+        assert!(
+            error.contains(&format!("unknown field `{field}`")),
+            "{error}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn explicit_config_format() -> anyhow::Result<()> {
+    let (path, _tmpdir) = write_config(
+        "gearbox.conf",
+        r#"instances = [{ transmission = { url = "x" }, policies = [] }]"#,
+    )?;
+    assert!(configure(&path).is_err());
+    let instances = configure_as(&path, ConfigFormat::Toml).map_err(|e| anyhow::anyhow!("{e}"))?;
+    assert_eq!(instances[0].name(), "x");
+    Ok(())
+}
+
+#[test]
+fn lint_policies() -> anyhow::Result<()> {
+    let (path, _tmpdir) = write_config(
+        "config.rhai",
+        r#"
+      let horse = on_trackers(["tracker.horse"]);
+      [
+        defaults().max_seeding_time("30 days"),
+        rules(transmission("x"), [
+          delete_policy("fine", on_trackers(["other.horse"]), matching().min_seeding_time("2 days")),
+          delete_policy("backwards", horse, matching().min_seeding_time("14 days").max_seeding_time("3 days")),
+          delete_policy("same", horse, matching().max_ratio(1.0)),
+          delete_policy("seasons", horse.min_file_count(2), matching().max_ratio(2.0)),
+          delete_policy("empty", on_trackers(["other.horse"]).min_file_count(5).max_file_count(2), matching().max_ratio(1.0)),
+        ]),
+      ]
+    "#,
+    )?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let findings: Vec<_> = lint(&instances)
+        .into_iter()
+        .map(|f| (f.severity, f.policies, f.message))
+        .collect();
+    let policies = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        findings,
+        vec![
+            (
+                Severity::Error,
+                policies(&["backwards"]),
+                "min_seeding_time (14d) is longer than max_seeding_time (3d), so torrents only get deleted after 14d".to_string()
+            ),
+            (
+                Severity::Error,
+                policies(&["empty"]),
+                "min_file_count (5) is greater than max_file_count (2), so the policy never applies".to_string()
+            ),
+            (
+                Severity::Error,
+                policies(&["backwards", "same"]),
+                "govern exactly the same torrents".to_string()
+            ),
+            (
+                Severity::Warning,
+                policies(&["backwards", "seasons"]),
+                "both govern torrents on tracker.horse with at least 2 files, and either may delete them".to_string()
+            ),
+            (
+                Severity::Warning,
+                policies(&["same", "seasons"]),
+                "both govern torrents on tracker.horse with at least 2 files, and either may delete them".to_string()
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn readme_example_lints_clean() -> anyhow::Result<()> {
+    let readme = include_str!("../README.md");
+    let example = readme
+        .split("```py\n")
+        .nth(1)
+        .and_then(|rest| rest.split("```").next())
+        .expect("README has a config example");
+    let (path, _tmpdir) = write_config("config.rhai", example)?;
+    let instances = configure(&path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    assert_eq!(lint(&instances), vec![]);
+    Ok(())
+}
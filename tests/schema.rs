@@ -0,0 +1,14 @@
+use gearbox_maintenance::config::format::json_schema;
+
+/// The published schema has to describe the current config types.
+/// Regenerate it with `cargo run -- schema > config.schema.json`.
+#[test]
+fn published_schema_is_up_to_date() {
+    let published = include_str!("../config.schema.json");
+    let current = serde_json::to_string_pretty(&json_schema()).unwrap();
+    assert_eq!(
+        published.trim_end(),
+        current,
+        "config.schema.json is out of date; regenerate it with `cargo run -- schema > config.schema.json`"
+    );
+}